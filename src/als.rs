@@ -0,0 +1,214 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Automatic brightness from an ambient light sensor.
+//!
+//! IIO sensors expose illuminance in sysfs, either already in lux or as a
+//! raw value with a scale and offset. sysfs offers no change notification
+//! for them, so the sensor is read on an interval. Readings are smoothed
+//! with a moving average and mapped to a level through the configured
+//! curve; the level only moves once the light is past a curve point by the
+//! hysteresis margin.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::config::AlsConfig;
+use crate::log::{debug, info, trace, warning};
+
+const IIO_DEVICES: &str = "/sys/bus/iio/devices";
+
+struct Sensor {
+    name: String,
+    path: PathBuf,
+    scale: f64,
+    offset: f64,
+}
+
+impl Sensor {
+    fn find() -> Option<Self> {
+        let mut dirs: Vec<PathBuf> = fs::read_dir(IIO_DEVICES)
+            .ok()?
+            .flatten()
+            .map(|e| e.path())
+            .collect();
+        dirs.sort();
+        dirs.into_iter().find_map(|dir| {
+            let name = read_trimmed(&dir.join("name")).unwrap_or_else(|| {
+                dir.file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+            let input = dir.join("in_illuminance_input");
+            if input.exists() {
+                return Some(Sensor {
+                    name,
+                    path: input,
+                    scale: 1.0,
+                    offset: 0.0,
+                });
+            }
+            let raw = dir.join("in_illuminance_raw");
+            raw.exists().then(|| Sensor {
+                name,
+                path: raw,
+                scale: read_number(&dir.join("in_illuminance_scale")).unwrap_or(1.0),
+                offset: read_number(&dir.join("in_illuminance_offset")).unwrap_or(0.0),
+            })
+        })
+    }
+
+    fn lux(&self) -> Option<f64> {
+        read_number(&self.path).map(|raw| ((raw + self.offset) * self.scale).max(0.0))
+    }
+}
+
+pub struct Als {
+    sensor: Sensor,
+    config: AlsConfig,
+    /// Smoothed illuminance.
+    lux: Option<f64>,
+    last_sample: Option<Instant>,
+    /// Curve point in effect.
+    point: Option<usize>,
+    /// Until when a manual change holds.
+    held_until: Option<Instant>,
+    failing: bool,
+}
+
+impl Als {
+    /// Finds the sensor. Returns `None` when auto brightness is disabled
+    /// or there is no sensor.
+    pub fn open(config: &AlsConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let Some(sensor) = Sensor::find() else {
+            warning!("no ambient light sensor found, auto brightness disabled");
+            return None;
+        };
+        info!("using {} for auto brightness", sensor.name);
+        Some(Als {
+            sensor,
+            config: config.clone(),
+            lux: None,
+            last_sample: None,
+            point: None,
+            held_until: None,
+            failing: false,
+        })
+    }
+
+    /// Takes new settings; the level is worked out afresh on the next
+    /// reading.
+    pub fn reconfigure(&mut self, config: &AlsConfig) {
+        self.config = config.clone();
+        self.point = None;
+    }
+
+    /// When the sensor is next due.
+    pub fn deadline(&self) -> Instant {
+        match self.last_sample {
+            Some(at) => at + self.config.interval,
+            None => Instant::now(),
+        }
+    }
+
+    /// Holds off automatic changes after someone set the brightness.
+    pub fn hold(&mut self) {
+        if self.held_until.is_none() {
+            debug!("brightness set by hand, pausing auto brightness");
+        }
+        self.held_until = Some(Instant::now() + self.config.hold);
+    }
+
+    /// Reads the sensor if due. Returns the level to switch to when it
+    /// changed, or when a manual hold just ended.
+    pub fn sample(&mut self) -> Option<u8> {
+        let now = Instant::now();
+        if self
+            .last_sample
+            .is_some_and(|at| now < at + self.config.interval)
+        {
+            return None;
+        }
+        let elapsed = self.last_sample.map(|at| now - at);
+        self.last_sample = Some(now);
+
+        let Some(reading) = self.sensor.lux() else {
+            if !self.failing {
+                warning!("cannot read {}", self.sensor.path.display());
+                self.failing = true;
+            }
+            return None;
+        };
+        self.failing = false;
+        let lux = match (self.lux, elapsed) {
+            (Some(lux), Some(elapsed)) if !self.config.smoothing.is_zero() => {
+                let alpha =
+                    1.0 - (-elapsed.as_secs_f64() / self.config.smoothing.as_secs_f64()).exp();
+                lux + alpha * (reading - lux)
+            }
+            _ => reading,
+        };
+        self.lux = Some(lux);
+        trace!(raw_lux = reading; "ambient light {lux:.1} lux");
+
+        let margin = 1.0 + self.config.hysteresis as f64 / 100.0;
+        let proposed = self.point_for(lux);
+        let point = match self.point {
+            None => proposed,
+            Some(current) if proposed > current => self.point_for(lux / margin).max(current),
+            Some(current) if proposed < current => self.point_for(lux * margin).min(current),
+            Some(current) => current,
+        };
+        let changed = self.point != Some(point);
+        self.point = Some(point);
+
+        let released = match self.held_until {
+            Some(until) if now >= until => {
+                self.held_until = None;
+                debug!("resuming auto brightness");
+                true
+            }
+            Some(_) => return None,
+            None => false,
+        };
+        let level = self.config.curve[point].1;
+        if changed {
+            debug!("ambient light {lux:.0} lux, brightness {level}");
+        }
+        (changed || released).then_some(level)
+    }
+
+    /// Last curve point at or below `lux`; the first one covers anything
+    /// darker.
+    fn point_for(&self, lux: f64) -> usize {
+        self.config
+            .curve
+            .iter()
+            .rposition(|&(at, _)| at <= lux)
+            .unwrap_or(0)
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn read_number(path: &Path) -> Option<f64> {
+    read_trimmed(path)?.parse().ok()
+}
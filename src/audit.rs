@@ -0,0 +1,281 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Audit trail of state changes: who asked for what, what the state was
+//! before and after, and whether it worked.
+//!
+//! Entries are appended to a file as JSON lines and rotated by size. The
+//! most recent ones are also kept in memory for the `HISTORY` verb.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::AuditConfig;
+use crate::error::Result;
+use crate::json::Value;
+use crate::log::warning;
+use crate::policy::Peer;
+
+/// Entries kept in memory and available through `HISTORY`.
+pub const RECENT: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub uid: u32,
+    pub pid: i32,
+    pub exe: String,
+    pub request: String,
+    pub old: String,
+    pub new: String,
+    /// `ok` or the error code name.
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+impl Entry {
+    pub fn new<T>(
+        peer: &Peer,
+        request: String,
+        old: String,
+        new: String,
+        result: &Result<T>,
+    ) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let (outcome, error) = match result {
+            Ok(_) => ("ok".to_string(), None),
+            Err(e) => (e.code().as_str().to_string(), Some(e.to_string())),
+        };
+        Entry {
+            time,
+            uid: peer.uid,
+            pid: peer.pid,
+            exe: peer.exe.clone(),
+            request,
+            old,
+            new,
+            outcome,
+            error,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut fields = vec![
+            ("time".into(), format_time(self.time).into()),
+            ("uid".into(), self.uid.into()),
+            ("pid".into(), (self.pid as i64).into()),
+            ("exe".into(), self.exe.as_str().into()),
+            ("request".into(), self.request.as_str().into()),
+            ("old".into(), self.old.as_str().into()),
+            ("new".into(), self.new.as_str().into()),
+            ("outcome".into(), self.outcome.as_str().into()),
+        ];
+        if let Some(error) = &self.error {
+            fields.push(("error".into(), error.as_str().into()));
+        }
+        Value::Object(fields)
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let text = |key| value.get(key).and_then(Value::as_str).map(String::from);
+        Some(Entry {
+            time: parse_time(value.get("time")?.as_str()?)?,
+            uid: u32::try_from(value.get("uid")?.as_i64()?).ok()?,
+            pid: i32::try_from(value.get("pid")?.as_i64()?).ok()?,
+            exe: text("exe")?,
+            request: text("request")?,
+            old: text("old")?,
+            new: text("new")?,
+            outcome: text("outcome")?,
+            error: text("error"),
+        })
+    }
+
+    /// One-line form used by the text protocol and the `history` command.
+    pub fn to_line(&self) -> String {
+        let mut line = format!(
+            "{} uid={} pid={} exe={} {}: {} -> {} ({})",
+            format_time(self.time),
+            self.uid,
+            self.pid,
+            self.exe,
+            self.request,
+            self.old,
+            self.new,
+            self.outcome
+        );
+        if let Some(error) = &self.error {
+            line.push_str(&format!(": {error}"));
+        }
+        line
+    }
+}
+
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    max_size: u64,
+    keep: u32,
+    file: Option<File>,
+    size: u64,
+    recent: VecDeque<Entry>,
+}
+
+impl AuditLog {
+    /// Opens the log and loads its latest entries. The file is opened
+    /// lazily, so a missing or unwritable directory only costs a warning
+    /// when the first entry is written.
+    pub fn open(config: &AuditConfig, path: PathBuf) -> Self {
+        let mut log = AuditLog {
+            path: config.enabled.then_some(path),
+            max_size: config.max_size,
+            keep: config.keep,
+            file: None,
+            size: 0,
+            recent: VecDeque::new(),
+        };
+        if let Some(path) = log.path.clone() {
+            // The previous file covers history lost in the last rotation.
+            for file in [rotated(&path, 1), path.clone()] {
+                let Ok(text) = fs::read_to_string(&file) else {
+                    continue;
+                };
+                for line in text.lines() {
+                    if let Some(entry) = Value::parse(line).ok().as_ref().and_then(Entry::from_json)
+                    {
+                        log.remember(entry);
+                    }
+                }
+                if file == path {
+                    log.size = text.len() as u64;
+                }
+            }
+        }
+        log
+    }
+
+    pub fn record(&mut self, entry: Entry) {
+        if let Err(e) = self.append(&entry) {
+            warning!("audit log: {e}");
+            self.file = None;
+        }
+        self.remember(entry);
+    }
+
+    /// Most recent entries, oldest first.
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &Entry> {
+        self.recent
+            .iter()
+            .skip(self.recent.len().saturating_sub(count))
+    }
+
+    fn remember(&mut self, entry: Entry) {
+        if self.recent.len() == RECENT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(entry);
+    }
+
+    fn append(&mut self, entry: &Entry) -> Result<()> {
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+        let line = format!("{}\n", entry.to_json());
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.file = None;
+            self.rotate(&path)?;
+        }
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(0o640)
+                .open(&path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Shifts `audit.log` to `audit.log.1` and so on, dropping the oldest.
+    fn rotate(&mut self, path: &Path) -> Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let _ = fs::rename(rotated(path, n), rotated(path, n + 1));
+            }
+            fs::rename(path, rotated(path, 1))?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Name of the `n`th rotated file, e.g. `audit.log.1`.
+fn rotated(path: &Path, n: u32) -> PathBuf {
+    PathBuf::from(format!("{}.{n}", path.display()))
+}
+
+/// Formats a Unix timestamp as UTC ISO 8601, e.g. `2026-10-19T03:04:05Z`.
+fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Howard Hinnant's days-to-civil algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Inverse of [`format_time`].
+fn parse_time(text: &str) -> Option<u64> {
+    let num = |range: std::ops::Range<usize>| text.get(range)?.parse::<i64>().ok();
+    if text.len() != 20 || !text.ends_with('Z') {
+        return None;
+    }
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, min, sec) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    u64::try_from(days * 86400 + hour * 3600 + min * 60 + sec).ok()
+}
@@ -13,7 +13,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::{config, protocol};
 
 #[derive(Parser)]
 #[command(
@@ -22,14 +26,63 @@ use clap::{Parser, Subcommand};
     about = "Gigabyte keyboard backlight control"
 )]
 pub struct Cli {
+    /// Control socket [default: from the config file, else /run/kbdlight/kbdlight.sock]
+    #[arg(long, global = true, env = "KBDLIGHT_SOCKET")]
+    pub socket: Option<PathBuf>,
+    /// Group allowed to use the socket [default: from the config file, else kbdlight]
+    #[arg(long, global = true)]
+    pub group: Option<String>,
     #[command(subcommand)]
     pub command: Command,
 }
 
+#[derive(Clone, Copy, Subcommand)]
+pub enum DaemonAction {
+    /// Stop the daemon named in the pidfile
+    Stop,
+    /// Report whether the daemon is running
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum ScheduleAction {
+    /// List the schedules with their state and next change (the default)
+    List,
+    /// Resume a paused schedule (admin only)
+    Enable { name: String },
+    /// Pause a schedule until it is enabled again or the daemon restarts (admin only)
+    Disable { name: String },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogTarget {
+    Stderr,
+    Syslog,
+    Journal,
+}
+
 #[derive(Subcommand)]
 pub enum Command {
     /// Run the privileged daemon
-    Daemon,
+    Daemon {
+        #[command(subcommand)]
+        action: Option<DaemonAction>,
+        /// Configuration file
+        #[arg(long, default_value = config::DEFAULT_PATH)]
+        config: PathBuf,
+        /// Fork into the background once started (logs to syslog)
+        #[arg(long, conflicts_with = "foreground")]
+        detach: bool,
+        /// Stay in the foreground; the default, for process supervisors
+        #[arg(long)]
+        foreground: bool,
+        /// Log destination [default: syslog if detached, journal under systemd, else stderr]
+        #[arg(long, value_enum)]
+        log: Option<LogTarget>,
+        /// Log level: error, warning, info, debug or trace [default: from the config file]
+        #[arg(long)]
+        log_level: Option<String>,
+    },
     /// Turn backlight on (max brightness)
     On,
     /// Turn backlight off
@@ -50,4 +103,45 @@ pub enum Command {
         #[arg(allow_hyphen_values = true)]
         delta: String,
     },
+    /// Set brightness and color together in a single transaction
+    Set {
+        /// Brightness level (0 = off, 9 = max)
+        #[arg(long)]
+        brightness: Option<u8>,
+        /// Color in RRGGBB or #RRGGBB format
+        #[arg(long)]
+        color: Option<String>,
+    },
+    /// Show protocol version and features supported by the daemon
+    Capabilities,
+    /// Show current brightness, color, mode and daemon health
+    Status,
+    /// Print state changes as they happen (until interrupted)
+    Subscribe,
+    /// Make the daemon re-read its configuration file (admin only)
+    Reload,
+    /// Print systemd unit files for socket activation
+    PrintUnits {
+        /// Configuration file the daemon will use
+        #[arg(long, default_value = config::DEFAULT_PATH)]
+        config: PathBuf,
+    },
+    /// Show or change the daemon's log level (changing it is admin only)
+    LogLevel {
+        /// New level: error, warning, info, debug or trace
+        level: Option<String>,
+    },
+    /// Show daemon statistics: requests, EC transactions, timeouts and latency
+    Stats,
+    /// Show, pause or resume time-of-day schedules
+    Schedule {
+        #[command(subcommand)]
+        action: Option<ScheduleAction>,
+    },
+    /// Show who changed the backlight recently
+    History {
+        /// Number of entries to show
+        #[arg(short = 'n', long, default_value_t = protocol::HISTORY_DEFAULT)]
+        count: usize,
+    },
 }
@@ -15,41 +15,189 @@
 
 use std::io::{BufRead, BufReader, Write};
 use std::os::unix::net::UnixStream;
+use std::path::Path;
 
-use crate::error::{Error, Result};
-use crate::protocol::{Request, SOCKET_PATH};
-
-pub fn send_command(request: &Request) -> Result<()> {
-    let mut stream = UnixStream::connect(SOCKET_PATH).map_err(|e| {
-        if e.kind() == std::io::ErrorKind::ConnectionRefused
-            || e.kind() == std::io::ErrorKind::NotFound
-        {
-            Error::DaemonNotRunning
-        } else if e.kind() == std::io::ErrorKind::PermissionDenied {
-            Error::Permission
-        } else {
-            Error::Io(e)
+use crate::error::{Error, ErrorCode, Result};
+use crate::protocol::{Capabilities, PROTOCOL_VERSION, Request, ScheduleCommand};
+
+struct Connection {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl Connection {
+    fn open(socket: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(socket).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::ConnectionRefused
+                || e.kind() == std::io::ErrorKind::NotFound
+            {
+                Error::DaemonNotRunning
+            } else if e.kind() == std::io::ErrorKind::PermissionDenied {
+                Error::Permission
+            } else {
+                Error::Io(e)
+            }
+        })?;
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok(Connection { stream, reader })
+    }
+
+    fn request(&mut self, request: &Request) -> Result<String> {
+        let line = format!("{}\n", request.to_line());
+        self.stream.write_all(line.as_bytes())?;
+        self.reply()
+    }
+
+    fn reply(&mut self) -> Result<String> {
+        let mut response = String::new();
+        if self.reader.read_line(&mut response)? == 0 {
+            return Err(Error::Protocol("daemon closed the connection".into()));
+        }
+        Ok(response.trim().to_string())
+    }
+
+    /// Opens a connection and performs the HELLO handshake, with `next`
+    /// sent right behind it so that both take a single round trip. Its reply
+    /// is then the next line to read. Daemons that predate the handshake
+    /// close the connection after rejecting it, so in that case a fresh
+    /// connection is returned without capabilities, and `next` still unsent.
+    fn handshake(socket: &Path, next: Option<&Request>) -> Result<(Self, Option<Capabilities>)> {
+        let mut conn = Self::open(socket)?;
+        let hello = Request::Hello {
+            version: PROTOCOL_VERSION,
+            json: false,
+        };
+        let mut lines = format!("{}\n", hello.to_line());
+        if let Some(next) = next {
+            lines.push_str(&format!("{}\n", next.to_line()));
         }
-    })?;
-
-    let line = format!("{}\n", request.to_line());
-    stream.write_all(line.as_bytes())?;
-
-    let mut reader = BufReader::new(&stream);
-    let mut response = String::new();
-    reader.read_line(&mut response)?;
-
-    let response = response.trim();
-    if response.starts_with("ERR") {
-        let msg = response.strip_prefix("ERR ").unwrap_or(response);
-        eprintln!("error: {msg}");
-        std::process::exit(1);
-    } else {
-        let msg = response.strip_prefix("OK ").unwrap_or(response);
-        if !msg.is_empty() && msg != "OK" {
-            println!("{msg}");
+        conn.stream.write_all(lines.as_bytes())?;
+        let response = conn.reply()?;
+        match check_response(&response) {
+            Ok(fields) => {
+                let caps = Capabilities::parse(fields)?;
+                if caps.protocol != PROTOCOL_VERSION {
+                    eprintln!(
+                        "warning: daemon {} speaks protocol {}, client speaks {PROTOCOL_VERSION}",
+                        caps.version, caps.protocol
+                    );
+                }
+                Ok((conn, Some(caps)))
+            }
+            Err(e) if e.code() != ErrorCode::Protocol => Err(e),
+            Err(_) => {
+                eprintln!(
+                    "warning: daemon does not support the protocol handshake; consider upgrading it"
+                );
+                Ok((Self::open(socket)?, None))
+            }
         }
     }
 
+    /// Opens a connection and sends `request` along with the handshake.
+    /// Returns the connection and the reply to `request`.
+    fn start(socket: &Path, request: &Request) -> Result<(Self, String)> {
+        let (mut conn, caps) = Self::handshake(socket, Some(request))?;
+        let response = match caps {
+            Some(_) => conn.reply()?,
+            None => conn.request(request)?,
+        };
+        Ok((conn, response))
+    }
+}
+
+/// Turns an `ERR <code> <message>` reply into an error. Daemons that predate
+/// error codes send `ERR <message>`; those map to an unclassified error.
+fn check_response(response: &str) -> Result<&str> {
+    let Some(rest) = response.strip_prefix("ERR") else {
+        return Ok(response.strip_prefix("OK ").unwrap_or(response));
+    };
+    let rest = rest.trim_start();
+    let (code, message) = match rest.split_once(' ') {
+        Some((name, message)) => match ErrorCode::from_name(name) {
+            Some(code) => (Some(code), message),
+            None => (None, rest),
+        },
+        None => (ErrorCode::from_name(rest), rest),
+    };
+    Err(Error::Remote {
+        code,
+        message: message.to_string(),
+    })
+}
+
+pub fn send_command(socket: &Path, request: &Request) -> Result<()> {
+    let (_, response) = Connection::start(socket, request)?;
+
+    let msg = check_response(&response)?;
+    if !msg.is_empty() && msg != "OK" {
+        println!("{msg}");
+    }
+
+    Ok(())
+}
+
+/// Prints the most recent audit log entries, oldest first.
+pub fn history(socket: &Path, count: usize) -> Result<()> {
+    read_list(socket, &Request::History(count), "ENTRY ")
+}
+
+/// Prints the daemon's statistics, one `name{labels} value` line each.
+pub fn stats(socket: &Path) -> Result<()> {
+    read_list(socket, &Request::Stats, "STAT ")
+}
+
+/// Prints the daemon's schedules, one per line.
+pub fn schedules(socket: &Path) -> Result<()> {
+    read_list(
+        socket,
+        &Request::Schedule(ScheduleCommand::List),
+        "SCHEDULE ",
+    )
+}
+
+/// Sends a request answered by `OK <count> ...` followed by `count` lines
+/// starting with `prefix`, and prints those lines without the prefix.
+fn read_list(socket: &Path, request: &Request, prefix: &str) -> Result<()> {
+    let (mut conn, response) = Connection::start(socket, request)?;
+    let msg = check_response(&response)?;
+    let count: usize = msg
+        .split_whitespace()
+        .next()
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| Error::Protocol(format!("unexpected reply: {msg}")))?;
+
+    for _ in 0..count {
+        let mut line = String::new();
+        if conn.reader.read_line(&mut line)? == 0 {
+            return Err(Error::Protocol("daemon closed the connection".into()));
+        }
+        let line = line.trim_end();
+        println!("{}", line.strip_prefix(prefix).unwrap_or(line));
+    }
     Ok(())
 }
+
+/// Subscribes to state changes and prints each event until the daemon goes away.
+pub fn subscribe(socket: &Path) -> Result<()> {
+    let (mut conn, response) = Connection::start(socket, &Request::Subscribe)?;
+    check_response(&response)?;
+
+    let stdout = std::io::stdout();
+    loop {
+        let mut line = String::new();
+        if conn.reader.read_line(&mut line)? == 0 {
+            return Err(Error::Protocol("daemon closed the connection".into()));
+        }
+        if let Some(event) = line.trim_end().strip_prefix("EVENT ") {
+            let mut out = stdout.lock();
+            writeln!(out, "{event}")?;
+            out.flush()?;
+        }
+    }
+}
+
+pub fn query_capabilities(socket: &Path) -> Result<Capabilities> {
+    let (_, caps) = Connection::handshake(socket, None)?;
+    caps.ok_or_else(|| Error::Protocol("daemon does not report capabilities".into()))
+}
@@ -0,0 +1,614 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Daemon configuration file.
+//!
+//! The format is INI-like: `[section]` headers, `key = value` lines and
+//! `#` comments. Lists are comma separated.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::log::Level;
+use crate::protocol::{GROUP_NAME, SOCKET_PATH};
+use crate::rules::{Action, Trigger};
+use crate::schedule::Schedule;
+
+pub const DEFAULT_PATH: &str = "/etc/gigabyte-kbd-backlight.conf";
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub daemon: DaemonConfig,
+    pub policy: PolicyConfig,
+    pub audit: AuditConfig,
+    pub ec: EcConfig,
+    pub stats: StatsConfig,
+    pub sleep: SleepConfig,
+    pub idle: IdleConfig,
+    pub lid: LidConfig,
+    pub power: PowerConfig,
+    pub als: AlsConfig,
+    pub rules: BTreeMap<Trigger, Action>,
+    /// Named schedules, in file order.
+    pub schedules: Vec<(String, Schedule)>,
+}
+
+/// Process-level settings: who the daemon runs as and how it is confined.
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    /// Unprivileged user to switch to once the EC and socket are open.
+    pub user: String,
+    pub state_dir: PathBuf,
+    /// Apply Landlock and seccomp restrictions after dropping root.
+    pub sandbox: bool,
+    /// Retain CAP_SYS_RAWIO (and access to /dev/port) after dropping root.
+    pub keep_rawio: bool,
+    /// Control socket; its directory holds the instance lock.
+    pub socket: PathBuf,
+    /// Group allowed to connect to the socket.
+    pub group: String,
+    /// Least severe log messages that are emitted.
+    pub log_level: Level,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            user: "kbdlightd".into(),
+            state_dir: PathBuf::from("/var/lib/gigabyte-kbd-backlight"),
+            sandbox: true,
+            keep_rawio: false,
+            socket: PathBuf::from(SOCKET_PATH),
+            group: GROUP_NAME.into(),
+            log_level: Level::Info,
+        }
+    }
+}
+
+/// Who may do what over the socket. Users and groups are given by name.
+#[derive(Debug, Clone)]
+pub struct PolicyConfig {
+    pub read_only_users: Vec<String>,
+    pub read_only_groups: Vec<String>,
+    pub admin_users: Vec<String>,
+    pub admin_groups: Vec<String>,
+    pub rate_limit: Option<Rate>,
+    /// Consult polkit for peers outside the socket group and for admin verbs.
+    pub polkit: bool,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        PolicyConfig {
+            read_only_users: Vec::new(),
+            read_only_groups: Vec::new(),
+            admin_users: vec!["root".into()],
+            admin_groups: Vec::new(),
+            rate_limit: None,
+            polkit: false,
+        }
+    }
+}
+
+/// Pacing of commands sent to the embedded controller.
+#[derive(Debug, Clone)]
+pub struct EcConfig {
+    /// Most EC commands per period; queued requests wait for a slot.
+    pub max_rate: Rate,
+}
+
+impl Default for EcConfig {
+    fn default() -> Self {
+        EcConfig {
+            max_rate: Rate {
+                count: 5,
+                per: Duration::from_secs(1),
+            },
+        }
+    }
+}
+
+/// Prometheus textfile export of the daemon statistics.
+#[derive(Debug, Clone)]
+pub struct StatsConfig {
+    /// File for node_exporter's textfile collector; not written when unset.
+    pub textfile: Option<PathBuf>,
+    /// How often the file is rewritten.
+    pub interval: Duration,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            textfile: None,
+            interval: Duration::from_secs(15),
+        }
+    }
+}
+
+/// What happens around system suspend, as announced by logind.
+#[derive(Debug, Clone)]
+pub struct SleepConfig {
+    /// Watch logind's `PrepareForSleep` signal.
+    pub enabled: bool,
+    /// Brightness to set before sleeping; `None` leaves the backlight alone.
+    pub before_sleep: Option<u8>,
+    /// Attempts at restoring the backlight after resume.
+    pub resume_attempts: u32,
+}
+
+impl Default for SleepConfig {
+    fn default() -> Self {
+        SleepConfig {
+            enabled: true,
+            before_sleep: None,
+            resume_attempts: 5,
+        }
+    }
+}
+
+/// Dimming the backlight when the keyboard has not been used for a while.
+#[derive(Debug, Clone)]
+pub struct IdleConfig {
+    /// Time without input before dimming; `None` disables the feature.
+    pub timeout: Option<Duration>,
+    /// Brightness while idle.
+    pub brightness: u8,
+    /// Time over which to fade down to `brightness`.
+    pub fade: Duration,
+    /// Count touchpad input as activity.
+    pub touchpad: bool,
+    /// Count keyboards other than the built-in one as activity.
+    pub external_keyboards: bool,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        IdleConfig {
+            timeout: None,
+            brightness: 0,
+            fade: Duration::from_secs(1),
+            touchpad: false,
+            external_keyboards: false,
+        }
+    }
+}
+
+/// What to do while the lid is closed.
+#[derive(Debug, Clone)]
+pub struct LidConfig {
+    pub enabled: bool,
+    /// Brightness limit while closed.
+    pub closed: u8,
+}
+
+impl Default for LidConfig {
+    fn default() -> Self {
+        LidConfig {
+            enabled: true,
+            closed: 0,
+        }
+    }
+}
+
+/// Limits while running on battery.
+#[derive(Debug, Clone)]
+pub struct PowerConfig {
+    /// Brightness limit on battery; 9 means none.
+    pub battery_brightness: u8,
+    /// Battery charge, in percent, below which the backlight goes off.
+    pub off_below: u8,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        PowerConfig {
+            battery_brightness: 9,
+            off_below: 0,
+        }
+    }
+}
+
+/// Brightness following an ambient light sensor.
+#[derive(Debug, Clone)]
+pub struct AlsConfig {
+    pub enabled: bool,
+    /// `(lux, level)` points in ascending lux order.
+    pub curve: Vec<(f64, u8)>,
+    /// Time between sensor readings.
+    pub interval: Duration,
+    /// Time constant of the moving average over readings.
+    pub smoothing: Duration,
+    /// Percent past a curve point the light must go to change level.
+    pub hysteresis: u32,
+    /// How long a manual brightness change holds.
+    pub hold: Duration,
+}
+
+impl Default for AlsConfig {
+    fn default() -> Self {
+        AlsConfig {
+            enabled: false,
+            curve: vec![(0.0, 2), (20.0, 1), (200.0, 0)],
+            interval: Duration::from_secs(1),
+            smoothing: Duration::from_secs(5),
+            hysteresis: 20,
+            hold: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Where accepted state changes are recorded.
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Log file; defaults to `audit.log` in the state directory.
+    pub path: Option<PathBuf>,
+    /// Size at which the file is rotated, in bytes.
+    pub max_size: u64,
+    /// Number of rotated files kept next to the current one.
+    pub keep: u32,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            enabled: true,
+            path: None,
+            max_size: 1024 * 1024,
+            keep: 3,
+        }
+    }
+}
+
+impl AuditConfig {
+    pub fn path(&self, daemon: &DaemonConfig) -> PathBuf {
+        self.path
+            .clone()
+            .unwrap_or_else(|| daemon.state_dir.join("audit.log"))
+    }
+}
+
+/// A request budget such as `20/s` or `600/min`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub count: u32,
+    pub per: Duration,
+}
+
+impl Rate {
+    fn parse(value: &str) -> Option<Self> {
+        let (count, unit) = value.split_once('/')?;
+        let per = match unit.trim() {
+            "s" | "sec" => Duration::from_secs(1),
+            "m" | "min" => Duration::from_secs(60),
+            "h" | "hour" => Duration::from_secs(3600),
+            _ => return None,
+        };
+        let count = count.trim().parse().ok().filter(|&n| n > 0)?;
+        Some(Rate { count, per })
+    }
+}
+
+impl Config {
+    /// Loads the config file. A missing file yields the defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                Self::parse(&text).map_err(|e| Error::Config(format!("{}: {e}", path.display())))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    pub fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut config = Config::default();
+        let mut section = String::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let n = n + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {n}: expected key = value"))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            match (section.as_str(), key) {
+                ("daemon", "user") => config.daemon.user = value.into(),
+                ("daemon", "state_dir") => config.daemon.state_dir = value.into(),
+                ("daemon", "sandbox") => config.daemon.sandbox = boolean(value, n)?,
+                ("daemon", "keep_rawio") => config.daemon.keep_rawio = boolean(value, n)?,
+                ("daemon", "socket") => config.daemon.socket = value.into(),
+                ("daemon", "group") => config.daemon.group = value.into(),
+                ("daemon", "log_level") => {
+                    config.daemon.log_level = Level::parse(value)
+                        .ok_or_else(|| format!("line {n}: unknown log level: {value}"))?
+                }
+                ("policy", "read_only_users") => config.policy.read_only_users = list(value),
+                ("policy", "read_only_groups") => config.policy.read_only_groups = list(value),
+                ("policy", "admin_users") => config.policy.admin_users = list(value),
+                ("policy", "admin_groups") => config.policy.admin_groups = list(value),
+                ("policy", "rate_limit") => {
+                    config.policy.rate_limit = Some(
+                        Rate::parse(value)
+                            .ok_or_else(|| format!("line {n}: invalid rate: {value}"))?,
+                    )
+                }
+                ("policy", "polkit") => config.policy.polkit = boolean(value, n)?,
+                ("ec", "max_rate") => {
+                    config.ec.max_rate = Rate::parse(value)
+                        .ok_or_else(|| format!("line {n}: invalid rate: {value}"))?
+                }
+                ("audit", "enabled") => config.audit.enabled = boolean(value, n)?,
+                ("audit", "path") => config.audit.path = Some(value.into()),
+                ("audit", "max_size") => config.audit.max_size = size(value, n)?,
+                ("audit", "keep") => {
+                    config.audit.keep = value
+                        .parse()
+                        .map_err(|_| format!("line {n}: invalid number: {value}"))?
+                }
+                ("stats", "textfile") => config.stats.textfile = Some(value.into()),
+                ("stats", "interval") => {
+                    config.stats.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .map(Duration::from_secs)
+                        .ok_or_else(|| format!("line {n}: invalid number of seconds: {value}"))?
+                }
+                ("sleep", "enabled") => config.sleep.enabled = boolean(value, n)?,
+                ("sleep", "before_sleep") => {
+                    config.sleep.before_sleep = match value {
+                        "keep" => None,
+                        "off" => Some(0),
+                        level => Some(level.parse().ok().filter(|&l| l <= 9).ok_or_else(|| {
+                            format!("line {n}: expected keep, off or 0-9, got {value}")
+                        })?),
+                    }
+                }
+                ("sleep", "resume_attempts") => {
+                    config.sleep.resume_attempts = value
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("line {n}: invalid number: {value}"))?
+                }
+                ("idle", "timeout") => {
+                    config.idle.timeout = Some(seconds(value, n)?).filter(|t| !t.is_zero())
+                }
+                ("idle", "brightness") => {
+                    config.idle.brightness = value
+                        .parse()
+                        .ok()
+                        .filter(|&l| l <= 9)
+                        .ok_or_else(|| format!("line {n}: expected 0-9, got {value}"))?
+                }
+                ("idle", "fade") => config.idle.fade = seconds(value, n)?,
+                ("idle", "touchpad") => config.idle.touchpad = boolean(value, n)?,
+                ("idle", "external_keyboards") => {
+                    config.idle.external_keyboards = boolean(value, n)?
+                }
+                ("power", "battery_brightness") => {
+                    config.power.battery_brightness = value
+                        .parse()
+                        .ok()
+                        .filter(|&l| l <= 9)
+                        .ok_or_else(|| format!("line {n}: expected 0-9, got {value}"))?
+                }
+                ("power", "off_below") => {
+                    config.power.off_below = value
+                        .trim_end_matches('%')
+                        .parse()
+                        .ok()
+                        .filter(|&p| p <= 100)
+                        .ok_or_else(|| format!("line {n}: expected a percentage, got {value}"))?
+                }
+                ("als", "enabled") => config.als.enabled = boolean(value, n)?,
+                ("als", "curve") => config.als.curve = curve(value, n)?,
+                ("als", "interval") => {
+                    config.als.interval = Some(seconds(value, n)?)
+                        .filter(|t| !t.is_zero())
+                        .ok_or_else(|| format!("line {n}: interval must be above 0"))?
+                }
+                ("als", "smoothing") => config.als.smoothing = seconds(value, n)?,
+                ("als", "hysteresis") => {
+                    config.als.hysteresis = value
+                        .trim_end_matches('%')
+                        .parse()
+                        .map_err(|_| format!("line {n}: expected a percentage, got {value}"))?
+                }
+                ("als", "hold") => config.als.hold = seconds(value, n)?,
+                ("lid", "enabled") => config.lid.enabled = boolean(value, n)?,
+                ("lid", "closed") => {
+                    config.lid.closed =
+                        match value {
+                            "off" => 0,
+                            level => level.parse().ok().filter(|&l| l <= 9).ok_or_else(|| {
+                                format!("line {n}: expected off or 0-9, got {value}")
+                            })?,
+                        }
+                }
+                ("rules", trigger) => {
+                    let trigger = Trigger::parse(trigger).ok_or_else(|| {
+                        format!(
+                            "line {n}: unknown trigger {trigger} (expected one of: {})",
+                            Trigger::NAMES.join(", ")
+                        )
+                    })?;
+                    let action = Action::parse(value).map_err(|e| format!("line {n}: {e}"))?;
+                    config.rules.insert(trigger, action);
+                }
+                ("schedule", name) => {
+                    if name.contains(char::is_whitespace) {
+                        return Err(format!("line {n}: schedule names cannot contain spaces"));
+                    }
+                    if config.schedules.iter().any(|(other, _)| other == name) {
+                        return Err(format!("line {n}: duplicate schedule {name}"));
+                    }
+                    let schedule = Schedule::parse(value).map_err(|e| format!("line {n}: {e}"))?;
+                    config.schedules.push((name.to_string(), schedule));
+                }
+                _ => return Err(format!("line {n}: unknown key {key} in [{section}]")),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn boolean(value: &str, line: usize) -> std::result::Result<bool, String> {
+    match value {
+        "yes" | "true" | "on" | "1" => Ok(true),
+        "no" | "false" | "off" | "0" => Ok(false),
+        _ => Err(format!("line {line}: expected yes or no, got {value}")),
+    }
+}
+
+/// Non-negative number of seconds, possibly fractional.
+fn seconds(value: &str, line: usize) -> std::result::Result<Duration, String> {
+    value
+        .parse()
+        .ok()
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        .ok_or_else(|| format!("line {line}: invalid number of seconds: {value}"))
+}
+
+/// Light curve: `lux:level` points separated by spaces, in ascending lux
+/// order.
+fn curve(value: &str, line: usize) -> std::result::Result<Vec<(f64, u8)>, String> {
+    let mut points: Vec<(f64, u8)> = Vec::new();
+    for point in value.split_whitespace() {
+        let parsed = point.split_once(':').and_then(|(lux, level)| {
+            let lux: f64 = lux.parse().ok().filter(|l: &f64| *l >= 0.0)?;
+            let level: u8 = level.parse().ok().filter(|&l| l <= 9)?;
+            Some((lux, level))
+        });
+        let Some((lux, level)) = parsed else {
+            return Err(format!(
+                "line {line}: expected lux:level (level 0-9), got {point}"
+            ));
+        };
+        if points.last().is_some_and(|&(last, _)| lux <= last) {
+            return Err(format!(
+                "line {line}: curve points must be in ascending lux order"
+            ));
+        }
+        points.push((lux, level));
+    }
+    if points.is_empty() {
+        return Err(format!("line {line}: empty curve"));
+    }
+    Ok(points)
+}
+
+/// Byte count with an optional `K` or `M` suffix.
+fn size(value: &str, line: usize) -> std::result::Result<u64, String> {
+    let (digits, scale) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], 1024),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 1024 * 1024),
+        _ => (value, 1),
+    };
+    let n = digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("line {line}: invalid size: {value}"))?;
+    n.checked_mul(scale)
+        .ok_or_else(|| format!("line {line}: size too large: {value}"))
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections_and_comments() {
+        let config = Config::parse(
+            "# comment\n\
+             ; another comment\n\
+             \n\
+             [daemon]\n\
+             sandbox = no\n\
+             [ audit ]\n\
+             \tenabled=yes\n\
+             keep = 3\n\
+             [policy]\n\
+             admin_users = alice, ,bob\n",
+        )
+        .unwrap();
+        assert!(!config.daemon.sandbox);
+        assert!(config.audit.enabled);
+        assert_eq!(config.audit.keep, 3);
+        assert_eq!(config.policy.admin_users, ["alice", "bob"]);
+    }
+
+    #[test]
+    fn reports_bad_lines_with_line_numbers() {
+        let cases = [
+            ("[daemon]\nsandbox\n", "line 2: expected key = value"),
+            ("[daemon]\nsandbox = maybe\n", "line 2: expected yes or no"),
+            (
+                "[daemon]\n\nbogus = 1\n",
+                "line 3: unknown key bogus in [daemon]",
+            ),
+            ("sandbox = no\n", "line 1: unknown key sandbox in []"),
+            ("[idle]\nbrightness = 10\n", "line 2: expected 0-9"),
+        ];
+        for (text, expected) in cases {
+            let err = Config::parse(text).unwrap_err();
+            assert!(err.starts_with(expected), "{text:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn parses_size_suffixes() {
+        assert_eq!(size("512", 1), Ok(512));
+        assert_eq!(size("4k", 1), Ok(4 * 1024));
+        assert_eq!(size("4 K", 1), Ok(4 * 1024));
+        assert_eq!(size("2M", 1), Ok(2 * 1024 * 1024));
+        assert!(size("0", 1).is_err());
+        assert!(size("M", 1).is_err());
+        assert!(size("2G", 1).is_err());
+        assert!(size("-1K", 1).is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_sizes() {
+        let err = Config::parse("[audit]\nmax_size = 99999999999999999M\n").unwrap_err();
+        assert_eq!(err, "line 2: size too large: 99999999999999999M");
+        assert_eq!(size(&format!("{}", u64::MAX), 1), Ok(u64::MAX));
+    }
+}
@@ -13,135 +13,1646 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::io::{BufRead, BufReader, Write};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{Read, Write};
+use std::os::fd::AsFd;
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
-use std::{fs, thread};
+use std::time::{Duration, Instant};
 
-use nix::unistd::{Group, Uid, chown};
+use nix::errno::Errno;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+use nix::unistd::{Group, User, getuid};
 
-use crate::ec::EcPort;
-use crate::error::{Error, Result};
-use crate::protocol::{self, GROUP_NAME, Request, SOCKET_PATH};
+use crate::als::Als;
+use crate::audit::{self, AuditLog, Entry};
+use crate::config::{Config, LidConfig, PowerConfig, Rate, SleepConfig};
+use crate::detach::Startup;
+use crate::ec::{self, EcPort};
+use crate::error::{Error, ErrorCode, Result};
+use crate::idle::Idle;
+use crate::json::Value;
+use crate::lid::Lid;
+use crate::log::{self, Level, debug, error, info, trace, warning};
+use crate::policy::{Peer, Policy};
+use crate::power::PowerState;
+use crate::protocol::{
+    self, Capabilities, Event, Framing, Health, Mode, PROTOCOL_VERSION, Request, Response,
+    ScheduleCommand,
+};
+use crate::ratelimit::TokenBucket;
+use crate::rules::{Action, Trigger};
+use crate::runtime::{Layout, Runtime};
+use crate::sandbox;
+use crate::schedule::{Schedule, Scheduler};
+use crate::sleep::SleepMonitor;
+use crate::stats::{self, Report, Stats};
+use crate::systemd::{self, Notifier};
+use crate::uevent::{DeviceEvent, UeventSocket};
+
+/// Upper bound on simultaneously connected clients (subscribers included).
+const MAX_CLIENTS: usize = 64;
+/// Longest request line accepted before the connection is dropped.
+const MAX_LINE: usize = 4096;
+/// Replies and events buffered for a slow reader before it is dropped.
+const MAX_PENDING_OUTPUT: usize = 64 * 1024;
+/// A connection that neither sends nor takes anything for this long is
+/// closed, unless it is subscribed or has a write queued.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Poll timeout; bounds how long a shutdown signal can go unnoticed.
+const POLL_INTERVAL_MS: u16 = 1000;
+/// Delay before the second attempt at restoring the backlight after resume;
+/// it doubles with every further attempt.
+const RESUME_RETRY: Duration = Duration::from_millis(250);
+/// How often a degraded daemon checks whether the EC has recovered.
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+/// Write requests waiting for the EC, in total and per user, before new ones
+/// are refused as throttled.
+const MAX_QUEUED: usize = 64;
+const MAX_QUEUED_PER_USER: usize = 8;
+/// Client id of queued requests the daemon makes itself, for `[rules]` and
+/// auto brightness.
+const DAEMON_CLIENT: u64 = u64::MAX;
+/// Audit name of requests made by auto brightness.
+const ALS_SOURCE: &str = "auto brightness";
 
 pub struct Daemon {
     ec: EcPort,
+    config_path: PathBuf,
+    policy: Policy,
     brightness: u8,
+    color: Option<(u8, u8, u8)>,
+    health: Health,
+    /// Last check of a degraded EC, or when it became degraded.
+    probed: Option<Instant>,
+    events: Vec<Event>,
+    audit: AuditLog,
+    /// Accepted write requests waiting for an EC slot.
+    queue: VecDeque<Queued>,
+    ec_rate: Rate,
+    ec_budget: TokenBucket,
+    next_client_id: u64,
+    /// Log level given on the command line, which wins over the config file.
+    log_override: Option<Level>,
+    stats: Stats,
+    sleep: Option<SleepMonitor>,
+    sleep_config: SleepConfig,
+    /// Pending restore of the backlight after resume.
+    resume: Option<Resume>,
+    idle: Option<Idle>,
+    lid: Option<Lid>,
+    lid_config: LidConfig,
+    power: Option<PowerState>,
+    power_config: PowerConfig,
+    uevents: Option<UeventSocket>,
+    rules: BTreeMap<Trigger, Action>,
+    als: Option<Als>,
+    schedule: Option<Scheduler>,
+}
+
+/// Progress of restoring the backlight after resume. Right after waking up
+/// the EC often leaves IBF set for a while, so failed attempts are retried
+/// with growing delays.
+#[derive(Debug, Clone, Copy)]
+struct Resume {
+    attempt: u32,
+    at: Instant,
+}
+
+/// A write request admitted by the policy and waiting for the EC. Its
+/// connection reads nothing further until the reply has been queued.
+struct Queued {
+    client: u64,
+    peer: Peer,
+    req: Request,
+    framing: Framing,
+    id: Option<Value>,
+}
+
+/// Single EC write within a `SET` transaction.
+enum Step {
+    Color((u8, u8, u8)),
+    Brightness(u8),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Color((r, g, b)) => write!(f, "color #{r:02X}{g:02X}{b:02X}"),
+            Step::Brightness(level) => write!(f, "brightness {level}"),
+        }
+    }
+}
+
+/// A connected peer. Sockets are non-blocking; input is split into lines and
+/// output is queued until the peer can take it.
+struct Client {
+    id: u64,
+    stream: UnixStream,
+    peer: Peer,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    framing: Option<Framing>,
+    subscribed: bool,
+    /// A write request from this connection is queued for the EC.
+    waiting: bool,
+    eof: bool,
+    failed: bool,
+    /// Last time the peer sent data or took some of its output.
+    active: Instant,
+}
+
+impl Client {
+    fn new(id: u64, stream: UnixStream) -> Result<Self> {
+        stream.set_nonblocking(true)?;
+        let peer = Peer::from_stream(&stream)?;
+        Ok(Client {
+            id,
+            stream,
+            peer,
+            input: Vec::new(),
+            output: Vec::new(),
+            framing: None,
+            subscribed: false,
+            waiting: false,
+            eof: false,
+            failed: false,
+            active: Instant::now(),
+        })
+    }
+
+    fn read_available(&mut self) {
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.eof = true;
+                    return;
+                }
+                Ok(n) => {
+                    self.input.extend_from_slice(&buf[..n]);
+                    self.active = Instant::now();
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.failed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        if self.waiting {
+            return None;
+        }
+        match self.input.iter().position(|&b| b == b'\n') {
+            Some(pos) => {
+                let line: Vec<u8> = self.input.drain(..=pos).collect();
+                Some(String::from_utf8_lossy(&line).into_owned())
+            }
+            None if self.input.len() > MAX_LINE => {
+                self.failed = true;
+                None
+            }
+            // A peer may close right after its last request without ending
+            // the line; serve what it sent.
+            None if self.eof && !self.input.is_empty() => {
+                let line: Vec<u8> = self.input.drain(..).collect();
+                Some(String::from_utf8_lossy(&line).into_owned())
+            }
+            None => None,
+        }
+    }
+
+    fn queue(&mut self, data: &str) {
+        if self.output.len() + data.len() > MAX_PENDING_OUTPUT {
+            self.failed = true;
+        } else {
+            self.output.extend_from_slice(data.as_bytes());
+        }
+    }
+
+    fn flush(&mut self) {
+        while !self.output.is_empty() && !self.failed {
+            match self.stream.write(&self.output) {
+                Ok(n) => {
+                    self.output.drain(..n);
+                    self.active = Instant::now();
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => self.failed = true,
+            }
+        }
+    }
+
+    /// Whether a complete request line is buffered and may be served now.
+    fn has_line(&self) -> bool {
+        !self.waiting && (self.input.contains(&b'\n') || (self.eof && !self.input.is_empty()))
+    }
+
+    fn done(&self) -> bool {
+        self.failed
+            || (self.eof && !self.waiting && self.input.is_empty() && self.output.is_empty())
+            || (!self.subscribed && !self.waiting && self.active.elapsed() >= CLIENT_TIMEOUT)
+    }
 }
 
 impl Daemon {
-    pub fn run() -> Result<()> {
-        let ec = EcPort::open()?;
-        let mut daemon = Daemon { ec, brightness: 9 };
+    /// Runs the daemon. `socket`, `group` and `log_level` override the config
+    /// file; `startup`, when detached, is told once the daemon is ready.
+    pub fn run(
+        config_path: &Path,
+        socket: Option<&Path>,
+        group: Option<&str>,
+        log_level: Option<Level>,
+        startup: &mut Option<Startup>,
+    ) -> Result<()> {
+        let mut config = Config::load(config_path)?;
+        if let Some(socket) = socket {
+            config.daemon.socket = socket.to_path_buf();
+        }
+        if let Some(group) = group {
+            config.daemon.group = group.to_string();
+        }
+        log::set_level(log_level.unwrap_or(config.daemon.log_level));
+        let layout = Self::layout(&config)?;
+        let audit_path = config.audit.path(&config.daemon);
+        let notifier = Notifier::from_env();
 
-        // Remove stale socket
-        let _ = fs::remove_file(SOCKET_PATH);
+        let ec = EcPort::open()?;
+        let mut daemon = Daemon {
+            ec,
+            config_path: config_path.to_path_buf(),
+            policy: Policy::new(&config.policy, layout.group.map(|g| g.as_raw())),
+            brightness: 9,
+            color: None,
+            health: Health::Ok,
+            probed: None,
+            events: Vec::new(),
+            audit: AuditLog::open(&config.audit, audit_path.clone()),
+            queue: VecDeque::new(),
+            ec_rate: config.ec.max_rate,
+            ec_budget: TokenBucket::new(config.ec.max_rate),
+            next_client_id: 0,
+            log_override: log_level,
+            stats: Stats::new(),
+            sleep: Self::connect_sleep(&config.sleep, None),
+            sleep_config: config.sleep.clone(),
+            resume: None,
+            idle: Idle::open(&config.idle),
+            lid: config.lid.enabled.then(Lid::open).flatten(),
+            lid_config: config.lid.clone(),
+            power: PowerState::read(),
+            power_config: config.power.clone(),
+            uevents: None,
+            rules: config.rules.clone(),
+            als: Als::open(&config.als),
+            schedule: None,
+        };
+        if let Some(power) = daemon.power {
+            info!("running {power}");
+        }
+        daemon.listen_uevents();
+        daemon.configure_schedules(&config.schedules);
 
-        let listener = UnixListener::bind(SOCKET_PATH)?;
-        listener.set_nonblocking(true)?;
+        // The socket lives in its own directory next to the instance lock,
+        // owned by the daemon user so it can clean up after dropping root.
+        // Under socket activation systemd owns it and guarantees a single
+        // instance.
+        let socket_path = config.daemon.socket.as_path();
+        let runtime_dir = socket_path.parent().unwrap_or(Path::new("/"));
+        let (runtime, listener) = match systemd::activated_listener()? {
+            Some(listener) => {
+                info!("using the socket passed by systemd");
+                (None, listener)
+            }
+            None => {
+                let (runtime, listener) = Runtime::setup(socket_path, &layout)?;
+                (Some(runtime), listener)
+            }
+        };
 
-        // Set socket permissions: root:kbdlight, 0660
-        Self::set_socket_permissions()?;
+        // Everything privileged is open now; shed root before serving peers.
+        let audit_dir = audit_path.parent().unwrap_or(Path::new("/"));
+        let textfile = config.stats.textfile.as_deref();
+        let mut writable = vec![runtime_dir, audit_dir];
+        writable.extend(textfile.and_then(Path::parent));
+        sandbox::enter(&config.daemon, &[config_path], &writable)?;
 
         // Register signal handlers
         let shutdown = Arc::new(AtomicBool::new(false));
         signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&shutdown))?;
         signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&shutdown))?;
 
-        eprintln!("daemon: listening on {SOCKET_PATH}");
+        if runtime.is_some() {
+            info!("listening on {}", socket_path.display());
+        }
+
+        // The EC is open and the socket bound: tell systemd we are up.
+        let mut status = daemon.status_text();
+        notifier.notify(&format!("READY=1\nSTATUS={status}"));
+        if let Some(startup) = startup.take() {
+            startup.ready();
+        }
+        let watchdog = notifier.watchdog_interval();
+        let mut last_ping = Instant::now();
+        if watchdog.is_some() {
+            notifier.notify("WATCHDOG=1");
+        }
+        let mut textfile_written: Option<Instant> = None;
+        let mut textfile_failing = false;
 
+        let mut clients: Vec<Client> = Vec::new();
         while !shutdown.load(Ordering::Relaxed) {
-            match listener.accept() {
-                Ok((stream, _)) => {
-                    if let Err(e) = daemon.handle_client(stream) {
-                        eprintln!("daemon: client error: {e}");
+            let (
+                listener_ready,
+                sleep_ready,
+                lid_ready,
+                uevent_ready,
+                schedule_ready,
+                input_ready,
+                client_ready,
+            ) = {
+                let mut fds = Vec::with_capacity(clients.len() + 2);
+                fds.push(PollFd::new(listener.as_fd(), PollFlags::POLLIN));
+                let sleep_slot = daemon.sleep.as_ref().map(|sleep| {
+                    fds.push(PollFd::new(sleep.as_fd(), PollFlags::POLLIN));
+                    fds.len() - 1
+                });
+                let lid_slot = daemon.lid.as_ref().and_then(Lid::fd).map(|fd| {
+                    fds.push(PollFd::new(fd, PollFlags::POLLIN));
+                    fds.len() - 1
+                });
+                let uevent_slot = daemon.uevents.as_ref().map(|uevents| {
+                    fds.push(PollFd::new(uevents.as_fd(), PollFlags::POLLIN));
+                    fds.len() - 1
+                });
+                let schedule_start = fds.len();
+                if let Some(schedule) = &daemon.schedule {
+                    fds.extend(schedule.fds().map(|fd| PollFd::new(fd, PollFlags::POLLIN)));
+                }
+                let input_start = fds.len();
+                if let Some(idle) = &daemon.idle {
+                    fds.extend(idle.fds().map(|fd| PollFd::new(fd, PollFlags::POLLIN)));
+                }
+                let client_start = fds.len();
+                // A closed peer keeps reporting POLLHUP; only wait for it to
+                // take its remaining output.
+                let client_slots: Vec<Option<usize>> = clients
+                    .iter()
+                    .map(|client| {
+                        let mut flags = PollFlags::empty();
+                        if !client.eof {
+                            flags |= PollFlags::POLLIN;
+                        }
+                        if !client.output.is_empty() {
+                            flags |= PollFlags::POLLOUT;
+                        }
+                        (!flags.is_empty()).then(|| {
+                            fds.push(PollFd::new(client.stream.as_fd(), flags));
+                            fds.len() - 1
+                        })
+                    })
+                    .collect();
+
+                // Wake up for the next EC slot while writes are queued, and
+                // right away if a request is already buffered.
+                let timeout = if clients.iter().any(Client::has_line)
+                    || daemon.sleep.as_ref().is_some_and(SleepMonitor::has_pending)
+                {
+                    PollTimeout::ZERO
+                } else if !daemon.queue.is_empty() {
+                    let wait = daemon.ec_budget.wait().as_millis().max(1);
+                    PollTimeout::try_from(wait.min(POLL_INTERVAL_MS.into()))
+                        .unwrap_or(PollTimeout::MAX)
+                } else {
+                    PollTimeout::from(POLL_INTERVAL_MS)
+                };
+                let timeout = match watchdog {
+                    Some(interval) if daemon.health == Health::Ok => {
+                        let due = interval.saturating_sub(last_ping.elapsed()).as_millis();
+                        timeout.min(PollTimeout::try_from(due).unwrap_or(PollTimeout::MAX))
+                    }
+                    _ => timeout,
+                };
+                let deadline = [
+                    daemon.resume.map(|r| r.at),
+                    daemon.idle.as_ref().and_then(Idle::deadline),
+                    daemon.als.as_ref().map(Als::deadline),
+                ]
+                .into_iter()
+                .flatten()
+                .min();
+                let timeout = match deadline {
+                    Some(at) => {
+                        let due = at.saturating_duration_since(Instant::now()).as_millis();
+                        timeout.min(PollTimeout::try_from(due).unwrap_or(PollTimeout::MAX))
                     }
+                    None => timeout,
+                };
+
+                match poll(&mut fds, timeout) {
+                    Ok(_) => {}
+                    Err(Errno::EINTR) => continue,
+                    Err(e) => return Err(Error::Io(e.into())),
                 }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    thread::sleep(Duration::from_millis(50));
+
+                let ready: Vec<bool> = fds
+                    .iter()
+                    .map(|fd| fd.revents().is_some_and(|r| !r.is_empty()))
+                    .collect();
+                (
+                    ready[0],
+                    sleep_slot.is_some_and(|i| ready[i]),
+                    lid_slot.is_some_and(|i| ready[i]),
+                    uevent_slot.is_some_and(|i| ready[i]),
+                    ready[schedule_start..input_start].contains(&true),
+                    ready[input_start..client_start].to_vec(),
+                    client_slots
+                        .iter()
+                        .map(|slot| slot.is_some_and(|i| ready[i]))
+                        .collect::<Vec<_>>(),
+                )
+            };
+
+            for (client, ready) in clients.iter_mut().zip(client_ready) {
+                if ready && !client.eof {
+                    client.read_available();
                 }
-                Err(e) => {
-                    eprintln!("daemon: accept error: {e}");
+            }
+
+            if listener_ready {
+                daemon.accept_clients(&listener, &mut clients);
+            }
+
+            if sleep_ready || daemon.sleep.as_ref().is_some_and(SleepMonitor::has_pending) {
+                daemon.handle_sleep();
+            }
+            if daemon.resume.is_some_and(|r| r.at <= Instant::now()) {
+                daemon.restore();
+            }
+            daemon.handle_lid(lid_ready);
+            if uevent_ready {
+                daemon.handle_uevents();
+            }
+            if schedule_ready {
+                daemon.run_schedules();
+            }
+            daemon.handle_input(&input_ready);
+            daemon.sample_light();
+
+            for client in &mut clients {
+                while let Some(line) = client.next_line() {
+                    daemon.handle_line(client, &line);
+                }
+            }
+
+            daemon.drain_queue(&mut clients);
+            daemon.probe_ec();
+
+            for event in daemon.events.drain(..) {
+                for client in clients.iter_mut().filter(|c| c.subscribed) {
+                    client.queue(&event.render(client.framing.unwrap_or(Framing::Text)));
+                }
+            }
+
+            for client in &mut clients {
+                client.flush();
+            }
+            clients.retain(|c| !c.done());
+
+            let current = daemon.status_text();
+            if current != status {
+                notifier.notify(&format!("STATUS={current}"));
+                status = current;
+            }
+            // Only a healthy EC keeps the watchdog fed, so systemd restarts a
+            // daemon stuck on an unresponsive controller.
+            if let Some(interval) = watchdog
+                && last_ping.elapsed() >= interval
+                && daemon.health == Health::Ok
+            {
+                notifier.notify("WATCHDOG=1");
+                last_ping = Instant::now();
+            }
+            if let Some(path) = textfile
+                && textfile_written.is_none_or(|t| t.elapsed() >= config.stats.interval)
+            {
+                // Complain once per failure streak, not on every interval.
+                match stats::write_textfile(path, &daemon.report()) {
+                    Ok(()) => textfile_failing = false,
+                    Err(e) if !textfile_failing => {
+                        warning!("cannot write {}: {e}", path.display());
+                        textfile_failing = true;
+                    }
+                    Err(_) => {}
                 }
+                textfile_written = Some(Instant::now());
             }
         }
 
-        eprintln!("daemon: shutting down");
-        let _ = fs::remove_file(SOCKET_PATH);
+        // Figures from a stopped daemon would look current to the collector.
+        if let Some(path) = textfile {
+            let _ = std::fs::remove_file(path);
+        }
+
+        notifier.notify("STOPPING=1");
+        info!("shutting down");
         Ok(())
     }
 
-    fn set_socket_permissions() -> Result<()> {
-        let gid = Group::from_name(GROUP_NAME)
+    /// Ownership for the runtime directory and socket: the daemon user and
+    /// the socket group. Peers outside the group may connect only when
+    /// polkit decides for them.
+    fn layout(config: &Config) -> Result<Layout> {
+        let owner = if getuid().is_root() {
+            User::from_name(&config.daemon.user)
+                .map_err(|e| Error::Io(e.into()))?
+                .map(|u| u.uid)
+        } else {
+            None
+        };
+        let group = Group::from_name(&config.daemon.group)
             .map_err(|e| Error::Io(e.into()))?
             .map(|g| g.gid);
+        if group.is_none() {
+            warning!(
+                "socket group {} does not exist, leaving the socket ungrouped",
+                config.daemon.group
+            );
+        }
+        let (dir_mode, socket_mode) = if config.policy.polkit {
+            (0o755, 0o666)
+        } else {
+            (0o750, 0o660)
+        };
+        Ok(Layout {
+            owner,
+            group,
+            dir_mode,
+            socket_mode,
+        })
+    }
+
+    fn accept_clients(&mut self, listener: &UnixListener, clients: &mut Vec<Client>) {
+        loop {
+            match listener.accept() {
+                Ok((mut stream, _)) => {
+                    if clients.len() >= MAX_CLIENTS {
+                        warning!("too many clients, rejecting connection");
+                        let busy = Error::Busy("too many clients".into());
+                        let _ = stream.write_all(
+                            protocol::render_error(&busy, Framing::Text, None).as_bytes(),
+                        );
+                        continue;
+                    }
+                    self.next_client_id += 1;
+                    match Client::new(self.next_client_id, stream) {
+                        Ok(mut client) => {
+                            // Serve whatever the peer sent along with connect.
+                            client.read_available();
+                            clients.push(client);
+                        }
+                        Err(e) => warning!("client error: {e}"),
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    error!("accept error: {e}");
+                    return;
+                }
+            }
+        }
+    }
 
-        chown(SOCKET_PATH, Some(Uid::from_raw(0)), gid).map_err(|e| Error::Io(e.into()))?;
+    fn handle_line(&mut self, client: &mut Client, line: &str) {
+        let framing = *client.framing.get_or_insert_with(|| Framing::detect(line));
 
-        use std::os::unix::fs::PermissionsExt;
-        fs::set_permissions(SOCKET_PATH, fs::Permissions::from_mode(0o660))?;
+        let (req, id) = match framing {
+            Framing::Text => (Request::parse(line), None),
+            Framing::Json => match Value::parse(line.trim()) {
+                Ok(value) => (Request::from_json(&value), value.get("id").cloned()),
+                Err(e) => (Err(e), None),
+            },
+        };
 
-        Ok(())
+        let req = match req {
+            Ok(req) => req,
+            Err(e) => {
+                self.stats.error(e.code());
+                debug!(
+                    peer_uid = client.peer.uid,
+                    peer_pid = client.peer.pid,
+                    error_code = e.code().as_str();
+                    "unparsable request: {e}"
+                );
+                return client.queue(&protocol::render_error(&e, framing, id.as_ref()));
+            }
+        };
+        debug!(
+            peer_uid = client.peer.uid,
+            peer_pid = client.peer.pid,
+            verb = req.verb();
+            "request {}", req.to_line()
+        );
+        self.stats.request(req.verb());
+
+        if let Err(e) = self.policy.admit(&client.peer, &req) {
+            self.stats.error(e.code());
+            warning!(
+                peer_uid = client.peer.uid,
+                peer_pid = client.peer.pid,
+                error_code = e.code().as_str();
+                "{} refused: {e}", req.verb()
+            );
+            return client.queue(&protocol::render_error(&e, framing, id.as_ref()));
+        }
+
+        if req.mutates() {
+            return self.enqueue(client, req, framing, id);
+        }
+
+        match self.execute(&req) {
+            Ok(response) => {
+                debug!(peer_uid = client.peer.uid, verb = req.verb(); "reply {}", response.message);
+                client.queue(&response.render(framing, id.as_ref()));
+                match req {
+                    // A `HELLO <version> JSON` handshake switches the rest of
+                    // the connection to JSON-lines once it has been acknowledged.
+                    Request::Hello { json: true, .. } => client.framing = Some(Framing::Json),
+                    Request::Subscribe => {
+                        client.subscribed = true;
+                        for event in self.snapshot() {
+                            client.queue(&event.render(framing));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Err(e) => {
+                self.stats.error(e.code());
+                debug!(
+                    peer_uid = client.peer.uid,
+                    verb = req.verb(),
+                    error_code = e.code().as_str();
+                    "failed: {e}"
+                );
+                client.queue(&protocol::render_error(&e, framing, id.as_ref()));
+            }
+        }
+    }
+
+    /// Queues a write request for the EC, or refuses it if the queue is full
+    /// or the peer's user already has too many requests waiting.
+    fn enqueue(&mut self, client: &mut Client, req: Request, framing: Framing, id: Option<Value>) {
+        let mine = self
+            .queue
+            .iter()
+            .filter(|q| q.peer.uid == client.peer.uid)
+            .count();
+        if self.queue.len() >= MAX_QUEUED || mine >= MAX_QUEUED_PER_USER {
+            let e = Error::Throttled("too many requests waiting for the EC".into());
+            self.stats.error(e.code());
+            warning!(
+                peer_uid = client.peer.uid,
+                peer_pid = client.peer.pid,
+                error_code = e.code().as_str();
+                "{} refused: {e}", req.verb()
+            );
+            return client.queue(&protocol::render_error(&e, framing, id.as_ref()));
+        }
+        client.waiting = true;
+        self.queue.push_back(Queued {
+            client: client.id,
+            peer: client.peer.clone(),
+            req,
+            framing,
+            id,
+        });
+    }
+
+    /// Executes queued writes as EC slots allow. Consecutive requests of the
+    /// same kind are merged into a single EC command, and every request in
+    /// the batch gets the merged outcome. The daemon's own requests are never
+    /// merged with clients', so they cannot overrule a client's reply.
+    fn drain_queue(&mut self, clients: &mut [Client]) {
+        while !self.queue.is_empty() && self.ec_budget.wait().is_zero() {
+            let mut batch = vec![self.queue.pop_front().unwrap()];
+            while let Some(next) = self.queue.front()
+                && next.req.verb() == batch[0].req.verb()
+                && (next.client == DAEMON_CLIENT) == (batch[0].client == DAEMON_CLIENT)
+            {
+                batch.extend(self.queue.pop_front());
+            }
+            let merged = coalesce(batch.iter().map(|q| &q.req));
+            // A client asking for a change counts as someone being around.
+            // Brightness changes are written below anyway, and hold off auto
+            // brightness for a while.
+            if batch.iter().any(|q| q.client != DAEMON_CLIENT) {
+                if sets_brightness(&merged) {
+                    if let Some(idle) = &mut self.idle {
+                        idle.activity();
+                    }
+                    // Levels auto brightness picked before the change
+                    // would undo it.
+                    if let Some(als) = &mut self.als {
+                        als.hold();
+                        self.queue
+                            .retain(|q| q.client != DAEMON_CLIENT || q.peer.exe != ALS_SOURCE);
+                    }
+                } else {
+                    self.wake();
+                }
+            }
+            debug!(
+                requests = batch.len(),
+                queued = self.queue.len();
+                "executing {}", merged.to_line()
+            );
+
+            let before = self.state_summary();
+            let sent = self.ec.stats().transactions;
+            let result = self.execute(&merged);
+            // A SET may take two commands, and a rollback more.
+            self.ec_budget.charge(self.ec.stats().transactions - sent);
+            let after = self.state_summary();
+            if let Err(e) = &result {
+                let uids: Vec<String> = batch.iter().map(|q| q.peer.uid.to_string()).collect();
+                error!(
+                    peer_uid = uids.join(","),
+                    error_code = e.code().as_str();
+                    "{} failed: {e}", merged.verb()
+                );
+            }
+
+            for queued in batch {
+                let entry = Entry::new(
+                    &queued.peer,
+                    queued.req.to_line(),
+                    before.clone(),
+                    after.clone(),
+                    &result,
+                );
+                self.audit.record(entry);
+                if let Err(e) = &result {
+                    self.stats.error(e.code());
+                }
+
+                let Some(client) = clients.iter_mut().find(|c| c.id == queued.client) else {
+                    continue;
+                };
+                client.waiting = false;
+                client.queue(&match &result {
+                    Ok(response) => response.render(queued.framing, queued.id.as_ref()),
+                    Err(e) => protocol::render_error(e, queued.framing, queued.id.as_ref()),
+                });
+            }
+        }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            protocol: PROTOCOL_VERSION,
+            version: env!("CARGO_PKG_VERSION").into(),
+            backend: ec::BACKEND.into(),
+            model: ec::MODEL_PROFILE.name.into(),
+            zones: ec::MODEL_PROFILE.zones,
+            verbs: protocol::VERBS.iter().map(|v| v.to_string()).collect(),
+        }
     }
 
-    fn handle_client(&mut self, stream: UnixStream) -> Result<()> {
-        let mut reader = BufReader::new(&stream);
-        let mut line = String::new();
-        reader.read_line(&mut line)?;
+    fn mode(&self) -> Mode {
+        if self.brightness > 0 {
+            Mode::On
+        } else {
+            Mode::Off
+        }
+    }
 
-        let response = match Request::parse(&line) {
-            Ok(req) => match self.execute(&req) {
-                Ok(msg) => msg,
-                Err(e) => protocol::err_response(&e.to_string()),
+    fn report(&self) -> Report {
+        Report::new(
+            &self.stats,
+            &stats::State {
+                brightness: self.brightness,
+                color: self
+                    .color
+                    .map(|(r, g, b)| format!("#{r:02X}{g:02X}{b:02X}")),
+                on: self.mode() == Mode::On,
+                healthy: self.health == Health::Ok,
+                queued: self.queue.len(),
+                ec: self.ec.stats(),
             },
-            Err(e) => protocol::err_response(&e.to_string()),
+        )
+    }
+
+    /// Current state as events, sent to a connection when it subscribes.
+    fn snapshot(&self) -> Vec<Event> {
+        let mut events = vec![Event::Brightness(self.brightness)];
+        if let Some((r, g, b)) = self.color {
+            events.push(Event::Color { r, g, b });
+        }
+        events.push(Event::Mode(self.mode()));
+        events.push(Event::Health(self.health.clone()));
+        events
+    }
+
+    /// One-line summary for the service manager's status display.
+    fn status_text(&self) -> String {
+        let mut text = format!("brightness {}/9", self.brightness);
+        if let Some((r, g, b)) = self.color {
+            text.push_str(&format!(", color #{r:02X}{g:02X}{b:02X}"));
+        }
+        text.push_str(&format!(", backend {}", ec::BACKEND));
+        if let Health::Degraded(reason) = &self.health {
+            text.push_str(&format!(", degraded: {reason}"));
+        }
+        text
+    }
+
+    /// Backlight state as recorded in the audit log.
+    fn state_summary(&self) -> String {
+        let color = match self.color {
+            Some((r, g, b)) => format!("#{r:02X}{g:02X}{b:02X}"),
+            None => "unknown".into(),
         };
+        format!("brightness={} color={color}", self.brightness)
+    }
 
-        let mut writer = stream;
-        writer.write_all(response.as_bytes())?;
-        Ok(())
+    fn status(&self) -> Response {
+        let color = match self.color {
+            Some((r, g, b)) => format!("#{r:02X}{g:02X}{b:02X}"),
+            None => "unknown".into(),
+        };
+        let mut response = Response::info(format!(
+            "{} mode={} health={}",
+            self.state_summary(),
+            self.mode(),
+            self.health
+        ))
+        .with("brightness", self.brightness)
+        .with("mode", self.mode().to_string());
+        response = match self.color {
+            Some(_) => response.with("color", color),
+            None => response.with("color", Value::Null),
+        };
+        response.with("health", self.health.to_string())
+    }
+
+    /// Applies a compound update: everything is validated before the EC is
+    /// touched, then written back to back. If a write fails, the steps that
+    /// already went through are reverted on a best-effort basis and the cached
+    /// state is left as it was.
+    fn transaction(
+        &mut self,
+        brightness: Option<u8>,
+        color: Option<(u8, u8, u8)>,
+    ) -> Result<Response> {
+        if brightness.is_none() && color.is_none() {
+            return Err(Error::Protocol("SET needs at least one field".into()));
+        }
+        if let Some(level) = brightness
+            && level > 9
+        {
+            return Err(Error::InvalidBrightness(level));
+        }
+
+        let (old_brightness, old_color) = (self.brightness, self.color);
+        let mut steps = Vec::new();
+        if let Some(rgb) = color {
+            steps.push(Step::Color(rgb));
+        }
+        if let Some(level) = brightness {
+            steps.push(Step::Brightness(level));
+        }
+
+        for (i, step) in steps.iter().enumerate() {
+            if let Err(e) = self.apply_step(step) {
+                for done in steps[..i].iter().rev() {
+                    let undo = match done {
+                        Step::Color(_) => old_color.map(Step::Color),
+                        Step::Brightness(_) => Some(Step::Brightness(old_brightness)),
+                    };
+                    if let Some(undo) = undo
+                        && let Err(undo_err) = self.apply_step(&undo)
+                    {
+                        error!(
+                            error_code = undo_err.code().as_str();
+                            "rollback failed: {undo_err}"
+                        );
+                    }
+                }
+                self.brightness = old_brightness;
+                self.color = old_color;
+                return Err(Error::Transaction {
+                    step: format!("{}/{} ({step})", i + 1, steps.len()),
+                    source: Box::new(e),
+                });
+            }
+        }
+
+        if let Some(rgb) = color {
+            self.color = Some(rgb);
+        }
+        if let Some(level) = brightness {
+            self.brightness = level;
+        }
+
+        let mut response = Response::info(format!("brightness {}/9", self.brightness))
+            .with("brightness", self.brightness);
+        if let Some((r, g, b)) = self.color {
+            let hex = format!("#{r:02X}{g:02X}{b:02X}");
+            response.message.push_str(&format!(" color {hex}"));
+            response = response.with("color", hex);
+        }
+        Ok(response)
+    }
+
+    fn apply_step(&mut self, step: &Step) -> Result<()> {
+        match *step {
+            Step::Color((r, g, b)) => self.ec.set_color(r, g, b),
+            Step::Brightness(level) => self.write_brightness(level),
+        }
+    }
+
+    /// Executes a request and queues events for every resulting state change.
+    fn execute(&mut self, req: &Request) -> Result<Response> {
+        let before = (self.brightness, self.color, self.mode());
+        let result = self.dispatch(req);
+
+        if self.brightness != before.0 {
+            self.events.push(Event::Brightness(self.brightness));
+        }
+        if self.color != before.1
+            && let Some((r, g, b)) = self.color
+        {
+            self.events.push(Event::Color { r, g, b });
+        }
+        if self.mode() != before.2 {
+            self.events.push(Event::Mode(self.mode()));
+        }
+
+        if req.mutates() {
+            self.update_health(&result);
+        }
+
+        result
+    }
+
+    /// Marks the daemon degraded while EC writes fail, and healthy again
+    /// once one succeeds.
+    fn update_health<T>(&mut self, result: &Result<T>) {
+        let health = match result {
+            Err(e) if e.code() == ErrorCode::EcTimeout || e.code() == ErrorCode::Io => {
+                Health::Degraded(e.to_string())
+            }
+            _ => Health::Ok,
+        };
+        if health != self.health {
+            if health != Health::Ok {
+                self.probed = Some(Instant::now());
+            }
+            self.health = health;
+            self.events.push(Event::Health(self.health.clone()));
+        }
+    }
+
+    /// Checks now and then whether a degraded EC takes input again. Health
+    /// otherwise only changes with writes, and without one it would stay
+    /// degraded, and the watchdog unfed, for good.
+    fn probe_ec(&mut self) {
+        if self.health == Health::Ok
+            || self.resume.is_some()
+            || self.probed.is_some_and(|at| at.elapsed() < PROBE_INTERVAL)
+        {
+            return;
+        }
+        self.probed = Some(Instant::now());
+        let result = self.ec.probe();
+        if result.is_ok() {
+            info!("the EC responds again");
+        }
+        self.update_health(&result);
+    }
+
+    /// Subscribes to logind's sleep notifications if configured, keeping an
+    /// existing connection.
+    fn connect_sleep(config: &SleepConfig, current: Option<SleepMonitor>) -> Option<SleepMonitor> {
+        if !config.enabled {
+            return None;
+        }
+        if current.is_some() {
+            return current;
+        }
+        match SleepMonitor::connect() {
+            Ok(monitor) => Some(monitor),
+            Err(e) => {
+                warning!("cannot watch for suspend, the backlight will not be restored: {e}");
+                None
+            }
+        }
+    }
+
+    /// Handles a message from logind. Before sleep the configured pre-sleep
+    /// state goes to the EC and the inhibitor is released; after resume the
+    /// wanted state is restored.
+    fn handle_sleep(&mut self) {
+        let Some(sleep) = &mut self.sleep else {
+            return;
+        };
+        let event = match sleep.next_event() {
+            Ok(Some(event)) => event,
+            Ok(None) => return,
+            Err(e) => {
+                warning!("lost the logind connection, no longer watching for suspend: {e}");
+                self.sleep = None;
+                return;
+            }
+        };
+
+        if event {
+            info!("preparing for sleep");
+            self.resume = None;
+            if let Some(level) = self.sleep_config.before_sleep
+                && let Err(e) = self.ec.set_brightness(level)
+            {
+                warning!(
+                    error_code = e.code().as_str();
+                    "cannot set the pre-sleep brightness: {e}"
+                );
+            }
+            sleep.release();
+        } else {
+            info!("resumed from sleep, restoring the backlight");
+            // The EC may have been reset; what was last sent proves nothing.
+            self.ec.invalidate();
+            sleep.inhibit();
+            // Whoever woke the machine is about to use it.
+            if let Some(idle) = &mut self.idle {
+                idle.activity();
+            }
+            self.resume = Some(Resume {
+                attempt: 0,
+                at: Instant::now(),
+            });
+            self.fire(Trigger::Resume);
+        }
+    }
+
+    /// Brightness the EC should show: the requested level, lowered while
+    /// the keyboard is idle, the lid is closed or the machine runs on
+    /// battery.
+    fn shown_brightness(&self, level: u8) -> u8 {
+        let lid = self
+            .lid
+            .as_ref()
+            .filter(|lid| self.lid_config.enabled && lid.closed())
+            .map(|_| self.lid_config.closed);
+        let power = self.power.filter(|p| !p.on_ac).map(|p| {
+            if p.capacity.is_some_and(|c| c < self.power_config.off_below) {
+                0
+            } else {
+                self.power_config.battery_brightness
+            }
+        });
+        [self.idle.as_ref().and_then(Idle::cap), lid, power]
+            .into_iter()
+            .flatten()
+            .fold(level, u8::min)
+    }
+
+    /// Sends a requested brightness to the EC, limited as the current
+    /// conditions demand.
+    fn write_brightness(&mut self, level: u8) -> Result<()> {
+        match self.shown_brightness(level) {
+            0 => self.ec.turn_off(),
+            9 => self.ec.turn_on(),
+            level => self.ec.set_brightness(level),
+        }
+    }
+
+    /// Runs `change`, then updates the EC if it altered what should be
+    /// shown.
+    fn relimit(&mut self, change: impl FnOnce(&mut Self)) {
+        let before = self.shown_brightness(self.brightness);
+        change(self);
+        if self.shown_brightness(self.brightness) == before {
+            return;
+        }
+        let result = self.write_brightness(self.brightness);
+        if let Err(e) = &result {
+            warning!(error_code = e.code().as_str(); "cannot update the brightness: {e}");
+        }
+        self.update_health(&result);
+    }
+
+    /// Lifts the idle limit, as after a keypress.
+    fn wake(&mut self) {
+        self.relimit(|daemon| {
+            if let Some(idle) = &mut daemon.idle {
+                idle.activity();
+            }
+        });
+    }
+
+    /// Follows the lid: the backlight goes off while it is closed and comes
+    /// back when it opens.
+    fn handle_lid(&mut self, ready: bool) {
+        let mut changed = false;
+        self.relimit(|daemon| {
+            if let Some(lid) = &mut daemon.lid {
+                changed = lid.update(ready);
+            }
+        });
+        if !changed {
+            return;
+        }
+        if self.lid.as_ref().is_some_and(Lid::closed) {
+            info!("lid closed");
+        } else {
+            info!("lid opened");
+            // Opening the lid means someone is about to type.
+            self.wake();
+        }
+    }
+
+    /// Opens the uevent socket if the battery or a rule needs it, and
+    /// closes it otherwise.
+    fn listen_uevents(&mut self) {
+        let needed = self.power.is_some() || self.rules.keys().any(|t| t.needs_uevents());
+        if !needed {
+            self.uevents = None;
+        } else if self.uevents.is_none() {
+            self.uevents = match UeventSocket::open() {
+                Ok(socket) => Some(socket),
+                Err(e) => {
+                    warning!(
+                        "cannot listen for kernel uevents, device changes will be missed: {e}"
+                    );
+                    None
+                }
+            };
+        }
+    }
+
+    /// Drains the uevent socket and reacts to what it reported.
+    fn handle_uevents(&mut self) {
+        let Some(uevents) = &self.uevents else {
+            return;
+        };
+        let mut events = Vec::new();
+        let mut power_changed = false;
+        loop {
+            match uevents.receive() {
+                Ok(Some(uevent)) => {
+                    trace!("uevent {}@{}", uevent.action, uevent.devpath);
+                    events.extend(uevent.kind());
+                }
+                Ok(None) => break,
+                Err(Errno::ENOBUFS) => {
+                    debug!("uevents were lost, re-reading the power supplies");
+                    power_changed = true;
+                }
+                Err(e) => {
+                    warning!("cannot receive kernel uevents: {e}");
+                    self.uevents = None;
+                    break;
+                }
+            }
+        }
+        for event in events {
+            match event {
+                DeviceEvent::PowerSupply => power_changed = true,
+                DeviceEvent::InputAdded(name) => {
+                    debug!("input device added: {name}");
+                    self.fire(Trigger::InputAdded);
+                }
+                DeviceEvent::InputRemoved(name) => {
+                    debug!("input device removed: {name}");
+                    self.fire(Trigger::InputRemoved);
+                }
+                DeviceEvent::DisplayChanged => {
+                    debug!("display connectors changed");
+                    self.fire(Trigger::DisplayChanged);
+                }
+            }
+        }
+        if power_changed {
+            self.update_power();
+        }
+    }
+
+    /// Re-reads the power supplies, applying the battery limits and firing
+    /// the `ac` and `battery` rules on a switch.
+    fn update_power(&mut self) {
+        let power = PowerState::read();
+        if power == self.power {
+            return;
+        }
+        let before = self.power.map(|p| p.on_ac);
+        match power {
+            Some(now) if before != Some(now.on_ac) => info!("now {now}"),
+            Some(now) => debug!("now {now}"),
+            None => info!("no battery any more"),
+        }
+        self.relimit(|daemon| daemon.power = power);
+        if let (Some(before), Some(now)) = (before, power)
+            && before != now.on_ac
+        {
+            self.fire(if now.on_ac {
+                Trigger::Ac
+            } else {
+                Trigger::Battery
+            });
+        }
+    }
+
+    /// Queues the action configured for `trigger`, if any, as if a client
+    /// had asked for it; it is rate limited and audited like one.
+    fn fire(&mut self, trigger: Trigger) {
+        let Some(action) = self.rules.get(&trigger).copied() else {
+            return;
+        };
+        info!("rule {trigger}: {action}");
+        self.queue_own(format!("rule {trigger}"), action.request());
+    }
+
+    /// Reads the light sensor when due and queues the brightness it calls
+    /// for.
+    fn sample_light(&mut self) {
+        let Some(level) = self.als.as_mut().and_then(Als::sample) else {
+            return;
+        };
+        if level != self.brightness {
+            self.queue_own(ALS_SOURCE.into(), Request::Brightness(level));
+        }
+    }
+
+    /// Sets up the scheduler for `schedules`, or hands it the new list.
+    /// Schedules in effect are applied right away.
+    fn configure_schedules(&mut self, schedules: &[(String, Schedule)]) {
+        match &mut self.schedule {
+            Some(scheduler) => scheduler.reconfigure(schedules),
+            None if schedules.is_empty() => return,
+            None => match Scheduler::new(schedules) {
+                Ok(scheduler) => self.schedule = Some(scheduler),
+                Err(e) => {
+                    warning!("cannot set up schedules: {e}");
+                    return;
+                }
+            },
+        }
+        self.run_schedules();
+    }
+
+    /// Queues what the schedules call for now.
+    fn run_schedules(&mut self) {
+        let Some(scheduler) = &mut self.schedule else {
+            return;
+        };
+        for (source, req) in scheduler.update((self.brightness, self.color)) {
+            self.queue_own(source, req);
+        }
+    }
+
+    /// Queues a request the daemon makes itself, on behalf of `source`. The
+    /// daemon gets the same share of the queue as a single user.
+    fn queue_own(&mut self, source: String, req: Request) {
+        let mine = self
+            .queue
+            .iter()
+            .filter(|q| q.client == DAEMON_CLIENT)
+            .count();
+        if self.queue.len() >= MAX_QUEUED || mine >= MAX_QUEUED_PER_USER {
+            warning!(
+                "too many requests waiting for the EC, dropping {source}: {}",
+                req.to_line()
+            );
+            return;
+        }
+        self.queue.push_back(Queued {
+            client: DAEMON_CLIENT,
+            peer: Peer::daemon(source),
+            req,
+            framing: Framing::Text,
+            id: None,
+        });
+    }
+
+    /// Reads the watched input devices and advances the idle timeout.
+    fn handle_input(&mut self, ready: &[bool]) {
+        let Some(idle) = &mut self.idle else {
+            return;
+        };
+        // Backwards, since a device that went away is removed.
+        let mut seen = false;
+        for (index, _) in ready.iter().enumerate().rev().filter(|(_, r)| **r) {
+            seen |= idle.read(index);
+        }
+        if seen {
+            self.wake();
+        }
+        self.relimit(|daemon| {
+            if let Some(idle) = &mut daemon.idle {
+                idle.tick(daemon.brightness);
+            }
+        });
+    }
+
+    /// Writes the wanted color and brightness back after resume, retrying
+    /// with growing delays while the EC is not ready.
+    fn restore(&mut self) {
+        let Some(resume) = self.resume.take() else {
+            return;
+        };
+        let mut result = Ok(());
+        if let Some((r, g, b)) = self.color {
+            result = self.ec.set_color(r, g, b);
+        }
+        if result.is_ok() {
+            result = self.write_brightness(self.brightness);
+        }
+
+        self.update_health(&result);
+        match &result {
+            Ok(()) => info!("restored {}", self.state_summary()),
+            Err(e) if resume.attempt + 1 < self.sleep_config.resume_attempts => {
+                let delay = RESUME_RETRY * 2u32.saturating_pow(resume.attempt);
+                warning!(
+                    error_code = e.code().as_str(),
+                    attempt = resume.attempt + 1;
+                    "cannot restore the backlight yet, retrying in {delay:?}: {e}"
+                );
+                self.resume = Some(Resume {
+                    attempt: resume.attempt + 1,
+                    at: Instant::now() + delay,
+                });
+            }
+            Err(e) => error!(
+                error_code = e.code().as_str(),
+                attempt = resume.attempt + 1;
+                "giving up restoring the backlight after resume: {e}"
+            ),
+        }
     }
 
-    fn execute(&mut self, req: &Request) -> Result<String> {
+    fn dispatch(&mut self, req: &Request) -> Result<Response> {
         match req {
+            Request::Hello { version, .. } => {
+                if *version != PROTOCOL_VERSION {
+                    info!("client speaks protocol {version}, daemon speaks {PROTOCOL_VERSION}");
+                }
+                let caps = Self::capabilities();
+                Ok(Response {
+                    message: caps.to_line(),
+                    result: caps.to_fields(),
+                    lines: Vec::new(),
+                })
+            }
             Request::On => {
-                self.ec.turn_on()?;
+                self.write_brightness(9)?;
                 self.brightness = 9;
-                Ok(protocol::ok_info_response("backlight on"))
+                Ok(Response::info("backlight on").with("brightness", self.brightness))
             }
             Request::Off => {
-                self.ec.turn_off()?;
+                self.write_brightness(0)?;
                 self.brightness = 0;
-                Ok(protocol::ok_info_response("backlight off"))
+                Ok(Response::info("backlight off").with("brightness", self.brightness))
             }
             Request::SetColor { r, g, b } => {
                 self.ec.set_color(*r, *g, *b)?;
-                Ok(protocol::ok_info_response(&format!(
-                    "color #{r:02X}{g:02X}{b:02X}"
-                )))
+                self.color = Some((*r, *g, *b));
+                let hex = format!("#{r:02X}{g:02X}{b:02X}");
+                Ok(Response::info(format!("color {hex}")).with("color", hex))
             }
             Request::Brightness(level) => {
                 if *level > 9 {
                     return Err(Error::InvalidBrightness(*level));
                 }
-                self.ec.set_brightness(*level)?;
+                self.write_brightness(*level)?;
                 self.brightness = *level;
-                Ok(protocol::ok_info_response(&format!(
-                    "brightness {}/9",
-                    self.brightness
-                )))
+                Ok(Response::info(format!("brightness {}/9", self.brightness))
+                    .with("brightness", self.brightness))
             }
             Request::AdjustBrightness(delta) => {
                 let new = (self.brightness as i16 + *delta as i16).clamp(0, 9) as u8;
-                self.ec.set_brightness(new)?;
+                self.write_brightness(new)?;
                 self.brightness = new;
-                Ok(protocol::ok_info_response(&format!(
-                    "brightness {}/9",
-                    self.brightness
-                )))
+                Ok(Response::info(format!("brightness {}/9", self.brightness))
+                    .with("brightness", self.brightness))
+            }
+            Request::Set { brightness, color } => self.transaction(*brightness, *color),
+            Request::Status => Ok(self.status()),
+            Request::Subscribe => Ok(Response::info("subscribed")),
+            Request::Reload => {
+                let config = Config::load(&self.config_path)?;
+                self.policy.reconfigure(&config.policy);
+                // Also a way to force the next write through.
+                self.ec.invalidate();
+                if config.ec.max_rate != self.ec_rate {
+                    self.ec_rate = config.ec.max_rate;
+                    self.ec_budget = TokenBucket::new(self.ec_rate);
+                }
+                log::set_level(self.log_override.unwrap_or(config.daemon.log_level));
+                self.sleep = Self::connect_sleep(&config.sleep, self.sleep.take());
+                self.sleep_config = config.sleep;
+                if let Some(idle) = &mut self.idle {
+                    idle.reconfigure(&config.idle);
+                }
+                let lid_config = config.lid;
+                let power_config = config.power;
+                self.relimit(|daemon| {
+                    daemon.lid_config = lid_config;
+                    daemon.power_config = power_config;
+                });
+                self.rules = config.rules;
+                self.listen_uevents();
+                self.configure_schedules(&config.schedules);
+                self.als = match (self.als.take(), config.als.enabled) {
+                    (Some(mut als), true) => {
+                        als.reconfigure(&config.als);
+                        Some(als)
+                    }
+                    (None, true) => Als::open(&config.als),
+                    (_, false) => None,
+                };
+                info!("reloaded {}", self.config_path.display());
+                Ok(Response::info("configuration reloaded"))
+            }
+            Request::History(count) => {
+                let entries: Vec<&Entry> = self.audit.recent((*count).min(audit::RECENT)).collect();
+                let mut response = Response::info(format!("{} entries", entries.len()));
+                response.lines = entries
+                    .iter()
+                    .map(|e| format!("ENTRY {}", e.to_line()))
+                    .collect();
+                Ok(response.with(
+                    "entries",
+                    Value::Array(entries.iter().map(|e| e.to_json()).collect()),
+                ))
+            }
+            Request::LogLevel(None) => {
+                let level = log::level();
+                Ok(Response::info(format!("log level {level}")).with("level", level.to_string()))
+            }
+            Request::Stats => {
+                let report = self.report();
+                let ec = self.ec.stats();
+                let lines = report.lines();
+                // Text clients learn from the count how many STAT lines follow.
+                let mut response = Response::info(format!(
+                    "{} metrics, up {}s, {} EC transactions, {} timeouts",
+                    lines.len(),
+                    self.stats.uptime().as_secs(),
+                    ec.transactions,
+                    ec.timeouts
+                ));
+                response.lines = lines.iter().map(|l| format!("STAT {l}")).collect();
+                Ok(response.with("metrics", report.to_json()))
+            }
+            Request::Schedule(ScheduleCommand::List) => {
+                let (lines, json) = match &self.schedule {
+                    Some(scheduler) => (scheduler.lines(), scheduler.to_json()),
+                    None => (Vec::new(), Value::Array(Vec::new())),
+                };
+                let mut response = Response::info(format!("{} schedules", lines.len()));
+                response.lines = lines.iter().map(|l| format!("SCHEDULE {l}")).collect();
+                Ok(response.with("schedules", json))
+            }
+            Request::Schedule(ScheduleCommand::Enable(name) | ScheduleCommand::Disable(name)) => {
+                let enable = matches!(req, Request::Schedule(ScheduleCommand::Enable(_)));
+                let found = self
+                    .schedule
+                    .as_mut()
+                    .is_some_and(|scheduler| scheduler.set_enabled(name, enable));
+                if !found {
+                    return Err(Error::InvalidArgument(format!("unknown schedule: {name}")));
+                }
+                self.run_schedules();
+                let state = if enable { "enabled" } else { "disabled" };
+                info!("schedule {name} {state}");
+                Ok(Response::info(format!("schedule {name} {state}")))
+            }
+            Request::LogLevel(Some(level)) => {
+                log::set_level(*level);
+                info!("log level set to {level}");
+                Ok(Response::info(format!("log level {level}")).with("level", level.to_string()))
             }
         }
     }
 }
+
+fn sets_brightness(req: &Request) -> bool {
+    match req {
+        Request::On | Request::Off | Request::Brightness(_) | Request::AdjustBrightness(_) => true,
+        Request::Set { brightness, .. } => brightness.is_some(),
+        _ => false,
+    }
+}
+
+/// Folds a run of requests with the same verb into one: adjustments are
+/// summed, `SET` fields are merged, and otherwise the latest request wins.
+fn coalesce<'a>(mut requests: impl Iterator<Item = &'a Request>) -> Request {
+    let mut merged = requests.next().expect("empty batch").clone();
+    for req in requests {
+        merged = match (merged, req) {
+            (Request::AdjustBrightness(a), Request::AdjustBrightness(b)) => {
+                Request::AdjustBrightness((a as i16 + *b as i16).clamp(-9, 9) as i8)
+            }
+            (
+                Request::Set { brightness, color },
+                Request::Set {
+                    brightness: b,
+                    color: c,
+                },
+            ) => Request::Set {
+                brightness: b.or(brightness),
+                color: c.or(color),
+            },
+            (_, req) => req.clone(),
+        };
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce_sums_adjustments() {
+        let batch = [
+            Request::AdjustBrightness(2),
+            Request::AdjustBrightness(3),
+            Request::AdjustBrightness(-1),
+        ];
+        assert_eq!(coalesce(batch.iter()), Request::AdjustBrightness(4));
+        let batch = [Request::AdjustBrightness(8), Request::AdjustBrightness(8)];
+        assert_eq!(coalesce(batch.iter()), Request::AdjustBrightness(9));
+        let batch = [Request::AdjustBrightness(-8), Request::AdjustBrightness(-8)];
+        assert_eq!(coalesce(batch.iter()), Request::AdjustBrightness(-9));
+    }
+
+    #[test]
+    fn coalesce_keeps_latest_color() {
+        let batch = [
+            Request::SetColor { r: 255, g: 0, b: 0 },
+            Request::SetColor { r: 0, g: 255, b: 0 },
+            Request::SetColor { r: 0, g: 0, b: 255 },
+        ];
+        assert_eq!(
+            coalesce(batch.iter()),
+            Request::SetColor { r: 0, g: 0, b: 255 }
+        );
+    }
+
+    #[test]
+    fn coalesce_merges_set_fields() {
+        let batch = [
+            Request::Set {
+                brightness: Some(3),
+                color: Some((1, 2, 3)),
+            },
+            Request::Set {
+                brightness: None,
+                color: Some((4, 5, 6)),
+            },
+            Request::Set {
+                brightness: Some(7),
+                color: None,
+            },
+        ];
+        assert_eq!(
+            coalesce(batch.iter()),
+            Request::Set {
+                brightness: Some(7),
+                color: Some((4, 5, 6)),
+            }
+        );
+    }
+
+    #[test]
+    fn coalesce_lets_latest_win_across_verbs() {
+        let batch = [Request::On, Request::Brightness(4), Request::Off];
+        assert_eq!(coalesce(batch.iter()), Request::Off);
+        let batch = [
+            Request::AdjustBrightness(2),
+            Request::Set {
+                brightness: Some(5),
+                color: None,
+            },
+        ];
+        assert_eq!(
+            coalesce(batch.iter()),
+            Request::Set {
+                brightness: Some(5),
+                color: None,
+            }
+        );
+    }
+}
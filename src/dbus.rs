@@ -0,0 +1,662 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Minimal synchronous D-Bus client: just enough of the wire protocol to
+//! call methods on system services and receive their signals. Only
+//! little-endian messages are understood, which covers every x86 peer this
+//! program talks to.
+
+use std::collections::VecDeque;
+use std::io::{IoSliceMut, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use nix::sys::socket::{ControlMessageOwned, MsgFlags, recvmsg};
+use nix::unistd::getuid;
+
+use crate::error::{Error, Result};
+
+const SYSTEM_BUS_PATH: &str = "/run/dbus/system_bus_socket";
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+pub const SIGNAL: u8 = 4;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+const FIELD_UNIX_FDS: u8 = 9;
+
+/// Most file descriptors accepted with a single read.
+const MAX_FDS: usize = 16;
+
+/// A D-Bus value. Arrays carry their element signature so that empty
+/// arrays can still be marshalled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Byte(u8),
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    Str(String),
+    Path(String),
+    Sig(String),
+    Variant(Box<Arg>),
+    Array(String, Vec<Arg>),
+    Struct(Vec<Arg>),
+    DictEntry(Box<Arg>, Box<Arg>),
+}
+
+impl Arg {
+    pub fn signature(&self) -> String {
+        match self {
+            Arg::Byte(_) => "y".into(),
+            Arg::Bool(_) => "b".into(),
+            Arg::I32(_) => "i".into(),
+            Arg::U32(_) => "u".into(),
+            Arg::I64(_) => "x".into(),
+            Arg::U64(_) => "t".into(),
+            Arg::Str(_) => "s".into(),
+            Arg::Path(_) => "o".into(),
+            Arg::Sig(_) => "g".into(),
+            Arg::Variant(_) => "v".into(),
+            Arg::Array(elem, _) => format!("a{elem}"),
+            Arg::Struct(fields) => {
+                format!(
+                    "({})",
+                    fields.iter().map(Arg::signature).collect::<String>()
+                )
+            }
+            Arg::DictEntry(k, v) => format!("{{{}{}}}", k.signature(), v.signature()),
+        }
+    }
+
+    /// Builds an `a{sv}` dictionary.
+    pub fn dict(entries: Vec<(&str, Arg)>) -> Arg {
+        Arg::Array(
+            "{sv}".into(),
+            entries
+                .into_iter()
+                .map(|(k, v)| {
+                    Arg::DictEntry(
+                        Box::new(Arg::Str(k.into())),
+                        Box::new(Arg::Variant(Box::new(v))),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Arg::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Arg::Str(s) | Arg::Path(s) | Arg::Sig(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Arg::U32(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn fields(&self) -> Option<&[Arg]> {
+        match self {
+            Arg::Struct(fields) => Some(fields),
+            _ => None,
+        }
+    }
+}
+
+fn alignment(code: u8) -> usize {
+    match code {
+        b'n' | b'q' => 2,
+        b'b' | b'i' | b'u' | b's' | b'o' | b'a' | b'h' => 4,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 1,
+    }
+}
+
+/// Length of the single complete type at the start of `sig`.
+fn type_len(sig: &[u8]) -> Result<usize> {
+    match sig.first() {
+        Some(b'a') => Ok(1 + type_len(&sig[1..])?),
+        Some(&open @ (b'(' | b'{')) => {
+            let close = if open == b'(' { b')' } else { b'}' };
+            let mut pos = 1;
+            while sig.get(pos) != Some(&close) {
+                if pos >= sig.len() {
+                    return Err(bad("unterminated container signature"));
+                }
+                pos += type_len(&sig[pos..])?;
+            }
+            Ok(pos + 1)
+        }
+        Some(_) => Ok(1),
+        None => Err(bad("empty signature")),
+    }
+}
+
+fn bad(msg: &str) -> Error {
+    Error::Dbus(format!("malformed message: {msg}"))
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn pad(&mut self, align: usize) {
+        while !self.buf.len().is_multiple_of(align) {
+            self.buf.push(0);
+        }
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.buf.push(s.len() as u8);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn arg(&mut self, arg: &Arg) {
+        match arg {
+            Arg::Byte(v) => self.buf.push(*v),
+            Arg::Bool(v) => self.u32(*v as u32),
+            Arg::I32(v) => self.u32(*v as u32),
+            Arg::U32(v) => self.u32(*v),
+            Arg::I64(v) => {
+                self.pad(8);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Arg::U64(v) => {
+                self.pad(8);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Arg::Str(s) | Arg::Path(s) => self.string(s),
+            Arg::Sig(s) => self.signature(s),
+            Arg::Variant(inner) => {
+                self.signature(&inner.signature());
+                self.arg(inner);
+            }
+            Arg::Array(elem, items) => {
+                self.u32(0);
+                let len_pos = self.buf.len() - 4;
+                self.pad(alignment(elem.as_bytes()[0]));
+                let start = self.buf.len();
+                for item in items {
+                    self.arg(item);
+                }
+                let len = (self.buf.len() - start) as u32;
+                self.buf[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+            }
+            Arg::Struct(fields) => {
+                self.pad(8);
+                for field in fields {
+                    self.arg(field);
+                }
+            }
+            Arg::DictEntry(k, v) => {
+                self.pad(8);
+                self.arg(k);
+                self.arg(v);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn align(&mut self, align: usize) {
+        self.pos = self.pos.div_ceil(align) * align;
+    }
+
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| bad("truncated body"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.align(4);
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        self.align(8);
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let s = String::from_utf8(self.take(len)?.to_vec()).map_err(|_| bad("invalid UTF-8"))?;
+        self.take(1)?;
+        Ok(s)
+    }
+
+    fn signature(&mut self) -> Result<String> {
+        let len = self.take(1)?[0] as usize;
+        let s = String::from_utf8(self.take(len)?.to_vec()).map_err(|_| bad("invalid UTF-8"))?;
+        self.take(1)?;
+        Ok(s)
+    }
+
+    /// Reads one value of the single complete type at the start of `sig`.
+    fn arg(&mut self, sig: &[u8]) -> Result<Arg> {
+        Ok(match sig[0] {
+            b'y' => Arg::Byte(self.take(1)?[0]),
+            b'b' => Arg::Bool(self.u32()? != 0),
+            b'n' | b'q' => {
+                self.align(2);
+                let v = self.take(2)?;
+                Arg::U32(u16::from_le_bytes([v[0], v[1]]) as u32)
+            }
+            b'i' => Arg::I32(self.u32()? as i32),
+            b'u' | b'h' => Arg::U32(self.u32()?),
+            b'x' => Arg::I64(self.u64()? as i64),
+            b't' | b'd' => Arg::U64(self.u64()?),
+            b's' => Arg::Str(self.string()?),
+            b'o' => Arg::Path(self.string()?),
+            b'g' => Arg::Sig(self.signature()?),
+            b'v' => {
+                let inner = self.signature()?;
+                if type_len(inner.as_bytes())? != inner.len() {
+                    return Err(bad("variant holds more than one type"));
+                }
+                Arg::Variant(Box::new(self.arg(inner.as_bytes())?))
+            }
+            b'a' => {
+                let elem = &sig[1..1 + type_len(&sig[1..])?];
+                let len = self.u32()? as usize;
+                self.align(alignment(elem[0]));
+                let end = self.pos + len;
+                let mut items = Vec::new();
+                while self.pos < end {
+                    items.push(self.arg(elem)?);
+                }
+                Arg::Array(String::from_utf8_lossy(elem).into_owned(), items)
+            }
+            open @ (b'(' | b'{') => {
+                self.align(8);
+                let inner = &sig[1..type_len(sig)? - 1];
+                let mut fields = Vec::new();
+                let mut pos = 0;
+                while pos < inner.len() {
+                    fields.push(self.arg(&inner[pos..])?);
+                    pos += type_len(&inner[pos..])?;
+                }
+                if open == b'{' {
+                    if fields.len() != 2 {
+                        return Err(bad("dict entry must have two fields"));
+                    }
+                    let v = fields.pop().unwrap();
+                    let k = fields.pop().unwrap();
+                    Arg::DictEntry(Box::new(k), Box::new(v))
+                } else {
+                    Arg::Struct(fields)
+                }
+            }
+            other => return Err(bad(&format!("unsupported type '{}'", other as char))),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Message {
+    pub kind: u8,
+    pub reply_serial: Option<u32>,
+    pub path: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub error_name: Option<String>,
+    pub sender: Option<String>,
+    pub signature: String,
+    pub body: Vec<u8>,
+    /// Descriptors passed with the message; `h` arguments index into this.
+    pub fds: Vec<OwnedFd>,
+    unix_fds: u32,
+}
+
+impl Message {
+    /// Decodes the body according to its signature.
+    pub fn args(&self) -> Result<Vec<Arg>> {
+        let sig = self.signature.as_bytes();
+        let mut reader = Reader {
+            buf: &self.body,
+            pos: 0,
+        };
+        let mut args = Vec::new();
+        let mut pos = 0;
+        while pos < sig.len() {
+            args.push(reader.arg(&sig[pos..])?);
+            pos += type_len(&sig[pos..])?;
+        }
+        Ok(args)
+    }
+
+    fn parse(header: &[u8], body: Vec<u8>) -> Result<Self> {
+        let mut msg = Message {
+            kind: header[1],
+            body,
+            ..Default::default()
+        };
+        let mut reader = Reader {
+            buf: header,
+            pos: 12,
+        };
+        let Arg::Array(_, fields) = reader.arg(b"a(yv)")? else {
+            unreachable!()
+        };
+        for field in fields {
+            let Some([Arg::Byte(code), Arg::Variant(value)]) = field.fields() else {
+                continue;
+            };
+            match (*code, value.as_ref()) {
+                (FIELD_PATH, Arg::Path(v)) => msg.path = Some(v.clone()),
+                (FIELD_INTERFACE, Arg::Str(v)) => msg.interface = Some(v.clone()),
+                (FIELD_MEMBER, Arg::Str(v)) => msg.member = Some(v.clone()),
+                (FIELD_ERROR_NAME, Arg::Str(v)) => msg.error_name = Some(v.clone()),
+                (FIELD_REPLY_SERIAL, Arg::U32(v)) => msg.reply_serial = Some(*v),
+                (FIELD_SENDER, Arg::Str(v)) => msg.sender = Some(v.clone()),
+                (FIELD_SIGNATURE, Arg::Sig(v)) => msg.signature = v.clone(),
+                (FIELD_UNIX_FDS, Arg::U32(v)) => msg.unix_fds = *v,
+                _ => {}
+            }
+        }
+        Ok(msg)
+    }
+}
+
+pub struct Connection {
+    stream: UnixStream,
+    serial: u32,
+    /// Messages that arrived while waiting for a method reply.
+    pending: VecDeque<Message>,
+    /// Descriptors received but not yet claimed by a message.
+    fds: VecDeque<OwnedFd>,
+}
+
+impl Connection {
+    /// Connects and authenticates to the system bus.
+    pub fn system() -> Result<Self> {
+        Self::system_with_timeout(CALL_TIMEOUT)
+    }
+
+    /// Like [`system`], but gives up on any reply, including those during
+    /// the handshake, after `timeout`.
+    ///
+    /// [`system`]: Connection::system
+    pub fn system_with_timeout(timeout: Duration) -> Result<Self> {
+        let path = std::env::var("DBUS_SYSTEM_BUS_ADDRESS")
+            .ok()
+            .and_then(|addr| {
+                addr.split(';')
+                    .find_map(|a| a.strip_prefix("unix:path=").map(String::from))
+            })
+            .unwrap_or_else(|| SYSTEM_BUS_PATH.into());
+
+        let mut stream = UnixStream::connect(&path)?;
+        stream.set_read_timeout(Some(timeout))?;
+
+        let uid: String = getuid()
+            .as_raw()
+            .to_string()
+            .bytes()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        stream.write_all(format!("\0AUTH EXTERNAL {uid}\r\n").as_bytes())?;
+        let reply = Self::read_auth_line(&mut stream)?;
+        if !reply.starts_with("OK ") {
+            return Err(Error::Dbus(format!("authentication rejected: {reply}")));
+        }
+        // Needed for methods that return descriptors, such as logind's
+        // Inhibit. A bus that refuses simply never sends any.
+        stream.write_all(b"NEGOTIATE_UNIX_FD\r\n")?;
+        Self::read_auth_line(&mut stream)?;
+        stream.write_all(b"BEGIN\r\n")?;
+
+        let mut conn = Connection {
+            stream,
+            serial: 0,
+            pending: VecDeque::new(),
+            fds: VecDeque::new(),
+        };
+        conn.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            &[],
+        )?;
+        Ok(conn)
+    }
+
+    fn read_auth_line(stream: &mut UnixStream) -> Result<String> {
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        while !line.ends_with(b"\r\n") {
+            if stream.read(&mut byte)? == 0 {
+                return Err(Error::Dbus("bus closed the connection".into()));
+            }
+            line.push(byte[0]);
+        }
+        Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+    }
+
+    /// Subscribes to the signals selected by a match rule such as
+    /// `type='signal',interface='org.freedesktop.login1.Manager'`.
+    pub fn add_match(&mut self, rule: &str) -> Result<()> {
+        self.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "AddMatch",
+            &[Arg::Str(rule.into())],
+        )?;
+        Ok(())
+    }
+
+    /// Whether messages are already buffered, so polling the socket would
+    /// not report them.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Returns the next buffered message, or reads one from the bus. Only
+    /// call this when the socket is readable or [`has_pending`] says so.
+    ///
+    /// [`has_pending`]: Connection::has_pending
+    pub fn next_message(&mut self) -> Result<Message> {
+        match self.pending.pop_front() {
+            Some(msg) => Ok(msg),
+            None => self.read_message(),
+        }
+    }
+
+    /// Calls a method and waits for its reply. D-Bus errors become
+    /// [`Error::Dbus`]; unrelated messages are kept for later.
+    pub fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Arg],
+    ) -> Result<Message> {
+        let serial = self.send(destination, path, interface, member, args)?;
+        loop {
+            let msg = self.read_message()?;
+            if msg.reply_serial != Some(serial) {
+                self.pending.push_back(msg);
+                continue;
+            }
+            return match msg.kind {
+                METHOD_RETURN => Ok(msg),
+                ERROR => {
+                    let detail = msg
+                        .args()
+                        .ok()
+                        .and_then(|a| a.first().and_then(|a| a.as_str().map(String::from)))
+                        .unwrap_or_default();
+                    Err(Error::Dbus(format!(
+                        "{}: {detail}",
+                        msg.error_name.as_deref().unwrap_or("error")
+                    )))
+                }
+                _ => Err(bad("unexpected reply type")),
+            };
+        }
+    }
+
+    fn send(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Arg],
+    ) -> Result<u32> {
+        self.serial += 1;
+
+        let mut body = Writer { buf: Vec::new() };
+        for arg in args {
+            body.arg(arg);
+        }
+        let signature: String = args.iter().map(Arg::signature).collect();
+
+        let field = |code: u8, value: Arg| {
+            Arg::Struct(vec![Arg::Byte(code), Arg::Variant(Box::new(value))])
+        };
+        let mut fields = vec![
+            field(FIELD_PATH, Arg::Path(path.into())),
+            field(FIELD_INTERFACE, Arg::Str(interface.into())),
+            field(FIELD_MEMBER, Arg::Str(member.into())),
+            field(FIELD_DESTINATION, Arg::Str(destination.into())),
+        ];
+        if !signature.is_empty() {
+            fields.push(field(FIELD_SIGNATURE, Arg::Sig(signature)));
+        }
+
+        let mut msg = Writer { buf: Vec::new() };
+        msg.buf.extend_from_slice(&[b'l', METHOD_CALL, 0, 1]);
+        msg.u32(body.buf.len() as u32);
+        msg.u32(self.serial);
+        msg.arg(&Arg::Array("(yv)".into(), fields));
+        msg.pad(8);
+        msg.buf.extend_from_slice(&body.buf);
+
+        self.stream.write_all(&msg.buf)?;
+        Ok(self.serial)
+    }
+
+    /// Like `read_exact`, but collects descriptors passed along the way.
+    fn receive(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let mut cmsg = nix::cmsg_space!([RawFd; MAX_FDS]);
+            let mut iov = [IoSliceMut::new(buf)];
+            let msg = recvmsg::<()>(
+                self.stream.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg),
+                MsgFlags::MSG_CMSG_CLOEXEC,
+            )
+            .map_err(|e| Error::Io(e.into()))?;
+            if msg.bytes == 0 {
+                return Err(Error::Dbus("bus closed the connection".into()));
+            }
+            let n = msg.bytes;
+            for cmsg in msg.cmsgs().map_err(|e| Error::Io(e.into()))? {
+                if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                    // SAFETY: the kernel just installed these descriptors
+                    // for us, and nothing else refers to them.
+                    self.fds.extend(
+                        fds.into_iter()
+                            .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+                    );
+                }
+            }
+            buf = &mut buf[n..];
+        }
+        Ok(())
+    }
+
+    fn read_message(&mut self) -> Result<Message> {
+        let mut fixed = [0u8; 16];
+        self.receive(&mut fixed)?;
+        if fixed[0] != b'l' {
+            return Err(bad("big-endian messages are not supported"));
+        }
+        let body_len = u32::from_le_bytes(fixed[4..8].try_into().unwrap()) as usize;
+        let fields_len = u32::from_le_bytes(fixed[12..16].try_into().unwrap()) as usize;
+        let header_len = (16 + fields_len).div_ceil(8) * 8;
+
+        let mut header = fixed.to_vec();
+        header.resize(header_len, 0);
+        self.receive(&mut header[16..])?;
+        let mut body = vec![0u8; body_len];
+        self.receive(&mut body)?;
+
+        let mut msg = Message::parse(&header, body)?;
+        for _ in 0..msg.unix_fds {
+            let fd = self
+                .fds
+                .pop_front()
+                .ok_or_else(|| bad("missing file descriptor"))?;
+            msg.fds.push(fd);
+        }
+        Ok(msg)
+    }
+}
+
+impl AsFd for Connection {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
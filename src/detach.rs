@@ -0,0 +1,145 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Traditional daemonization for inits without a service manager: double
+//! fork, pidfile, and `daemon stop`/`daemon status` built on the pidfile.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::fcntl::OFlag;
+use nix::sys::signal::{Signal, kill};
+use nix::sys::stat::{Mode, umask};
+use nix::sys::wait::waitpid;
+use nix::unistd::{ForkResult, Pid, chdir, dup2, fork, pipe2, setsid};
+
+use crate::error::{Error, Result};
+
+/// How long `daemon stop` waits for the daemon to exit.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pidfile kept next to the socket and the instance lock.
+pub fn pidfile(socket: &Path) -> PathBuf {
+    socket.with_extension("pid")
+}
+
+/// Reports the outcome of startup to the process that ran `daemon --detach`,
+/// which waits for it before exiting.
+pub struct Startup {
+    pipe: File,
+}
+
+impl Startup {
+    pub fn ready(mut self) {
+        let _ = self.pipe.write_all(b"OK\n");
+    }
+
+    pub fn failed(mut self, error: &Error) {
+        let _ = writeln!(self.pipe, "{} {error}", error.exit_status());
+    }
+}
+
+/// Forks twice so the daemon is reparented to init and can never regain a
+/// controlling terminal, then points stdio at /dev/null. Only the daemon
+/// process returns; the original process exits once startup has succeeded
+/// or failed, with the daemon's exit status.
+pub fn detach() -> Result<Startup> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::Io(e.into()))?;
+
+    // SAFETY: the daemon has not started any threads yet.
+    match unsafe { fork() }.map_err(|e| Error::Io(e.into()))? {
+        ForkResult::Parent { child } => {
+            drop(write);
+            let _ = waitpid(child, None);
+            let mut reply = String::new();
+            let _ = File::from(read).read_to_string(&mut reply);
+            match reply.trim_end() {
+                "OK" => std::process::exit(0),
+                "" => {
+                    eprintln!("error: daemon exited during startup");
+                    std::process::exit(1);
+                }
+                failure => {
+                    let (status, message) = failure.split_once(' ').unwrap_or(("1", failure));
+                    eprintln!("error: {message}");
+                    std::process::exit(status.parse().unwrap_or(1));
+                }
+            }
+        }
+        ForkResult::Child => {}
+    }
+    drop(read);
+
+    setsid().map_err(|e| Error::Io(e.into()))?;
+    // SAFETY: as above; the session leader exits right away.
+    if let ForkResult::Parent { .. } = unsafe { fork() }.map_err(|e| Error::Io(e.into()))? {
+        std::process::exit(0);
+    }
+
+    chdir("/").map_err(|e| Error::Io(e.into()))?;
+    umask(Mode::from_bits_truncate(0o022));
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    for fd in 0..=2 {
+        dup2(null.as_raw_fd(), fd).map_err(|e| Error::Io(e.into()))?;
+    }
+
+    Ok(Startup {
+        pipe: File::from(write),
+    })
+}
+
+/// PID of the running daemon according to the pidfile, or `None` if the
+/// file is missing or stale.
+pub fn running(socket: &Path) -> Result<Option<Pid>> {
+    let text = match fs::read_to_string(pidfile(socket)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::Io(e)),
+    };
+    let Ok(pid) = text.trim().parse::<i32>() else {
+        return Ok(None);
+    };
+    // The PID may have been reused since; make sure it is still us.
+    let ours = fs::read_to_string("/proc/self/comm")?;
+    match fs::read_to_string(format!("/proc/{pid}/comm")) {
+        Ok(comm) if comm == ours => Ok(Some(Pid::from_raw(pid))),
+        _ => Ok(None),
+    }
+}
+
+/// Sends SIGTERM to the daemon and waits for it to go away.
+pub fn stop(socket: &Path) -> Result<()> {
+    let pid = running(socket)?.ok_or(Error::DaemonNotRunning)?;
+    kill(pid, Signal::SIGTERM).map_err(|e| match e {
+        nix::errno::Errno::EPERM => Error::Permission,
+        e => Error::Io(e.into()),
+    })?;
+
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while Instant::now() < deadline {
+        if kill(pid, None).is_err() {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Err(Error::Busy(format!(
+        "daemon (pid {pid}) did not exit within {}s",
+        STOP_TIMEOUT.as_secs()
+    )))
+}
@@ -19,6 +19,8 @@ use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::error::{Error, Result};
+use crate::log::{debug, trace};
+use crate::stats::EcStats;
 
 const EC_DATA_PORT: u64 = 0x62;
 const EC_CMD_PORT: u64 = 0x66;
@@ -27,10 +29,45 @@ const EC_FDAT: u8 = 0xF9;
 const EC_FBUF: u8 = 0xFA;
 const EC_FBF1: u8 = 0xFB;
 const EC_FBF2: u8 = 0xFC;
+const EC_OBF: u8 = 0x01;
 const EC_IBF: u8 = 0x02;
 
+/// Argument registers of the command mailbox, in the order they are written.
+const MAILBOX: [u8; 4] = [EC_FDAT, EC_FBUF, EC_FBF1, EC_FBF2];
+/// How long a command may be taken as still in effect. Firmware hotkeys
+/// change the backlight without going through the mailbox, so a repeat is
+/// only skipped shortly after the original.
+const REPEAT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Access path used to reach the EC.
+pub const BACKEND: &str = "/dev/port";
+
+/// Static description of the laptop model this utility drives.
+pub struct ModelProfile {
+    pub name: &'static str,
+    pub zones: u8,
+    /// Whether the backlight takes a color, rather than a fixed white.
+    pub rgb: bool,
+}
+
+pub const MODEL_PROFILE: ModelProfile = ModelProfile {
+    name: "G6X9MG",
+    zones: 1,
+    rgb: true,
+};
+
+/// A mailbox command: FCMD plus its arguments (FDAT, FBUF, FBF1, FBF2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Command {
+    fcmd: u8,
+    args: [Option<u8>; 4],
+}
+
 pub struct EcPort {
     file: File,
+    stats: EcStats,
+    /// Last command that went through, and when.
+    last: Option<(Command, Instant)>,
 }
 
 impl EcPort {
@@ -38,7 +75,7 @@ impl EcPort {
         let file = OpenOptions::new()
             .read(true)
             .write(true)
-            .open("/dev/port")
+            .open(BACKEND)
             .map_err(|e| {
                 if e.kind() == std::io::ErrorKind::PermissionDenied {
                     Error::Permission
@@ -46,7 +83,11 @@ impl EcPort {
                     Error::Io(e)
                 }
             })?;
-        Ok(EcPort { file })
+        Ok(EcPort {
+            file,
+            stats: EcStats::default(),
+            last: None,
+        })
     }
 
     fn port_read(&mut self, port: u64) -> Result<u8> {
@@ -62,27 +103,98 @@ impl EcPort {
         Ok(())
     }
 
-    fn ec_wait_ibf(&mut self) -> Result<()> {
+    fn ec_wait_ibf(&mut self, reg: u8) -> Result<()> {
         let deadline = Instant::now() + Duration::from_millis(200);
         while Instant::now() < deadline {
             if self.port_read(EC_CMD_PORT)? & EC_IBF == 0 {
                 return Ok(());
             }
+            self.stats.retries += 1;
             thread::sleep(Duration::from_millis(1));
         }
-        Err(Error::EcTimeout)
+        Err(Error::EcTimeout { register: reg })
+    }
+
+    fn ec_wait_obf(&mut self, reg: u8) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_millis(200);
+        while Instant::now() < deadline {
+            if self.port_read(EC_CMD_PORT)? & EC_OBF != 0 {
+                return Ok(());
+            }
+            self.stats.retries += 1;
+            thread::sleep(Duration::from_millis(1));
+        }
+        Err(Error::EcTimeout { register: reg })
+    }
+
+    fn ec_read(&mut self, reg: u8) -> Result<u8> {
+        self.ec_wait_ibf(reg)?;
+        self.port_write(EC_CMD_PORT, 0x80)?;
+        self.ec_wait_ibf(reg)?;
+        self.port_write(EC_DATA_PORT, reg)?;
+        self.ec_wait_obf(reg)?;
+        self.port_read(EC_DATA_PORT)
     }
 
     fn ec_write(&mut self, reg: u8, val: u8) -> Result<()> {
-        self.ec_wait_ibf()?;
+        self.ec_wait_ibf(reg)?;
         self.port_write(EC_CMD_PORT, 0x81)?;
-        self.ec_wait_ibf()?;
+        self.ec_wait_ibf(reg)?;
         self.port_write(EC_DATA_PORT, reg)?;
-        self.ec_wait_ibf()?;
+        self.ec_wait_ibf(reg)?;
         self.port_write(EC_DATA_PORT, val)?;
         Ok(())
     }
 
+    /// Checks that the EC accepts input, without sending it anything.
+    pub fn probe(&mut self) -> Result<()> {
+        self.ec_wait_ibf(EC_FCMD)
+    }
+
+    pub fn stats(&self) -> &EcStats {
+        &self.stats
+    }
+
+    /// Forgets the last command, so that the next one is sent even if it is
+    /// a repeat, e.g. after resume, when the EC may have been reset.
+    pub fn invalidate(&mut self) {
+        self.last = None;
+    }
+
+    /// Whether `cmd` repeats the last command, sent recently, and the
+    /// mailbox read back still holds its arguments. The kernel's ACPI code
+    /// shares the mailbox, so anything else means the EC may have run other
+    /// commands since, and the last command is forgotten.
+    fn already_applied(&mut self, cmd: &Command) -> bool {
+        if !self
+            .last
+            .is_some_and(|(last, at)| last == *cmd && at.elapsed() < REPEAT_WINDOW)
+        {
+            return false;
+        }
+        for (i, reg) in MAILBOX.into_iter().enumerate() {
+            let Some(expected) = cmd.args[i] else {
+                continue;
+            };
+            match self.ec_read(reg) {
+                Ok(actual) if actual == expected => {}
+                Ok(actual) => {
+                    debug!(
+                        "EC register {reg:#04X} is {actual:#04X}, expected {expected:#04X}; resending"
+                    );
+                    self.invalidate();
+                    return false;
+                }
+                Err(e) => {
+                    debug!("cannot read back EC register {reg:#04X}: {e}; resending");
+                    self.invalidate();
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
     fn ec_cmd(
         &mut self,
         fcmd: u8,
@@ -91,24 +203,57 @@ impl EcPort {
         fbf1: Option<u8>,
         fbf2: Option<u8>,
     ) -> Result<()> {
-        if let Some(v) = fdat {
-            self.ec_write(EC_FDAT, v)?;
-        }
-        if let Some(v) = fbuf {
-            self.ec_write(EC_FBUF, v)?;
+        let cmd = Command {
+            fcmd,
+            args: [fdat, fbuf, fbf1, fbf2],
+        };
+        if self.already_applied(&cmd) {
+            self.stats.skipped += 1;
+            trace!(fcmd = format!("{fcmd:#04X}"); "EC command already applied, skipped");
+            return Ok(());
         }
-        if let Some(v) = fbf1 {
-            self.ec_write(EC_FBF1, v)?;
+        let started = Instant::now();
+        let result = self.send(&cmd);
+        self.last = result.is_ok().then(|| (cmd, Instant::now()));
+        self.stats.transactions += 1;
+        self.stats.latency.observe(started.elapsed());
+        if let Err(Error::EcTimeout { .. }) = result {
+            self.stats.timeouts += 1;
         }
-        if let Some(v) = fbf2 {
-            self.ec_write(EC_FBF2, v)?;
+        trace!(
+            fcmd = format!("{fcmd:#04X}"),
+            args = cmd
+                .args
+                .map(|a| a.map_or("--".into(), |v| format!("{v:02X}")))
+                .join(" "),
+            elapsed_us = started.elapsed().as_micros(),
+            ok = result.is_ok();
+            "EC command took {:?}", started.elapsed()
+        );
+        result
+    }
+
+    /// Writes every argument the command has, then FCMD to trigger it. The
+    /// kernel's ACPI code shares the mailbox, so nothing written earlier can
+    /// be assumed to still be there.
+    fn send(&mut self, cmd: &Command) -> Result<()> {
+        for (i, reg) in MAILBOX.into_iter().enumerate() {
+            if let Some(v) = cmd.args[i] {
+                self.ec_write(reg, v)?;
+            }
         }
-        self.ec_write(EC_FCMD, fcmd)?;
+        self.ec_write(EC_FCMD, cmd.fcmd)?;
         thread::sleep(Duration::from_millis(50));
         Ok(())
     }
 
     pub fn set_color(&mut self, r: u8, g: u8, b: u8) -> Result<()> {
+        if !MODEL_PROFILE.rgb {
+            return Err(Error::Unsupported(format!(
+                "{} has no RGB backlight",
+                MODEL_PROFILE.name
+            )));
+        }
         // BGR order: FBUF=B, FBF1=R, FBF2=G
         self.ec_cmd(0xCA, Some(0x03), Some(b), Some(r), Some(g))
     }
@@ -20,32 +20,160 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
-    EcTimeout,
+    EcTimeout { register: u8 },
     InvalidBrightness(u8),
     InvalidColor(String),
+    InvalidArgument(String),
     Protocol(String),
     Permission,
     DaemonNotRunning,
+    Unsupported(String),
+    Busy(String),
+    Denied(String),
+    Throttled(String),
+    Config(String),
+    Dbus(String),
+    Transaction { step: String, source: Box<Error> },
+    Remote { code: Option<ErrorCode>, message: String },
+}
+
+/// Stable, machine-readable error classes. The name is sent in text `ERR`
+/// replies, the number in JSON-lines replies, and the CLI exits with a
+/// distinct status for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Io,
+    EcTimeout,
+    InvalidArgument,
+    Protocol,
+    Permission,
+    DaemonNotRunning,
+    Unsupported,
+    Busy,
+    Denied,
+    Throttled,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 10] = [
+        ErrorCode::Io,
+        ErrorCode::EcTimeout,
+        ErrorCode::InvalidArgument,
+        ErrorCode::Protocol,
+        ErrorCode::Permission,
+        ErrorCode::DaemonNotRunning,
+        ErrorCode::Unsupported,
+        ErrorCode::Busy,
+        ErrorCode::Denied,
+        ErrorCode::Throttled,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Io => "io",
+            ErrorCode::EcTimeout => "ec-timeout",
+            ErrorCode::InvalidArgument => "invalid-argument",
+            ErrorCode::Protocol => "protocol",
+            ErrorCode::Permission => "permission",
+            ErrorCode::DaemonNotRunning => "daemon-not-running",
+            ErrorCode::Unsupported => "unsupported",
+            ErrorCode::Busy => "busy",
+            ErrorCode::Denied => "denied",
+            ErrorCode::Throttled => "throttled",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == name)
+    }
+
+    pub fn number(self) -> u16 {
+        match self {
+            ErrorCode::Io => 1,
+            ErrorCode::EcTimeout => 2,
+            ErrorCode::InvalidArgument => 3,
+            ErrorCode::Protocol => 4,
+            ErrorCode::Permission => 5,
+            ErrorCode::DaemonNotRunning => 6,
+            ErrorCode::Unsupported => 7,
+            ErrorCode::Busy => 8,
+            ErrorCode::Denied => 9,
+            ErrorCode::Throttled => 10,
+        }
+    }
+
+    /// Process exit status used by the CLI. 2 matches clap's usage errors.
+    pub fn exit_status(self) -> i32 {
+        match self {
+            ErrorCode::Io => 1,
+            ErrorCode::InvalidArgument => 2,
+            ErrorCode::DaemonNotRunning => 3,
+            ErrorCode::Permission => 4,
+            ErrorCode::EcTimeout => 5,
+            ErrorCode::Unsupported => 6,
+            ErrorCode::Busy => 7,
+            ErrorCode::Protocol => 8,
+            ErrorCode::Denied => 9,
+            ErrorCode::Throttled => 10,
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Io(e) => write!(f, "I/O error: {e}"),
-            Error::EcTimeout => write!(f, "EC timeout (IBF stuck)"),
+            Error::EcTimeout { register } => write!(f, "EC timeout (IBF stuck) writing register 0x{register:02X}"),
             Error::InvalidBrightness(v) => write!(f, "invalid brightness level: {v} (expected 0-9)"),
             Error::InvalidColor(s) => write!(f, "invalid color: {s}"),
+            Error::InvalidArgument(s) => write!(f, "invalid argument: {s}"),
             Error::Protocol(s) => write!(f, "protocol error: {s}"),
             Error::Permission => write!(f, "permission denied"),
             Error::DaemonNotRunning => write!(f, "daemon not running (cannot connect to socket)"),
+            Error::Unsupported(s) => write!(f, "unsupported: {s}"),
+            Error::Busy(s) => write!(f, "busy: {s}"),
+            Error::Denied(s) => write!(f, "denied: {s}"),
+            Error::Throttled(s) => write!(f, "throttled: {s}"),
+            Error::Config(s) => write!(f, "config error: {s}"),
+            Error::Dbus(s) => write!(f, "D-Bus error: {s}"),
+            Error::Transaction { step, source } => write!(f, "transaction step {step} failed: {source}"),
+            Error::Remote { message, .. } => write!(f, "{message}"),
         }
     }
 }
 
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Io(_) | Error::Dbus(_) => ErrorCode::Io,
+            Error::EcTimeout { .. } => ErrorCode::EcTimeout,
+            Error::InvalidBrightness(_)
+            | Error::InvalidColor(_)
+            | Error::InvalidArgument(_)
+            | Error::Config(_) => ErrorCode::InvalidArgument,
+            Error::Protocol(_) => ErrorCode::Protocol,
+            Error::Permission => ErrorCode::Permission,
+            Error::DaemonNotRunning => ErrorCode::DaemonNotRunning,
+            Error::Unsupported(_) => ErrorCode::Unsupported,
+            Error::Busy(_) => ErrorCode::Busy,
+            Error::Denied(_) => ErrorCode::Denied,
+            Error::Throttled(_) => ErrorCode::Throttled,
+            Error::Transaction { source, .. } => source.code(),
+            // Replies from daemons that predate error codes carry no class.
+            Error::Remote { code, .. } => code.unwrap_or(ErrorCode::Io),
+        }
+    }
+
+    pub fn exit_status(&self) -> i32 {
+        self.code().exit_status()
+    }
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Error::Io(e) => Some(e),
+            Error::Transaction { source, .. } => Some(source.as_ref()),
             _ => None,
         }
     }
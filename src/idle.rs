@@ -0,0 +1,184 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Keyboard idle timeout driven by evdev activity.
+//!
+//! The built-in keyboard sits behind the i8042 controller; its event device
+//! is found through sysfs and opened before the daemon drops root. Once no
+//! input has been seen for the configured time the backlight is dimmed,
+//! one level per step when fading, and the first event lifts the limit.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::time::Instant;
+
+use crate::config::IdleConfig;
+use crate::input::{self, BUS_I8042, EV_ABS, EV_KEY, EV_REL, EV_REP_BIT, EVENT_SIZE, PROP_POINTER};
+use crate::log::{debug, info, warning};
+
+struct Device {
+    file: File,
+    name: String,
+}
+
+pub struct Idle {
+    devices: Vec<Device>,
+    config: IdleConfig,
+    last_activity: Instant,
+    /// Brightness limit while idle, lowered step by step during a fade.
+    cap: Option<u8>,
+    next_step: Instant,
+}
+
+impl Idle {
+    /// Opens the input devices to watch. Returns `None` when the timeout
+    /// is disabled or no suitable device exists.
+    pub fn open(config: &IdleConfig) -> Option<Self> {
+        config.timeout?;
+        let devices = discover(config);
+        if devices.is_empty() {
+            warning!("no keyboard to watch, idle timeout disabled");
+            return None;
+        }
+        for device in &devices {
+            info!("watching {} for keyboard activity", device.name);
+        }
+        let now = Instant::now();
+        Some(Idle {
+            devices,
+            config: config.clone(),
+            last_activity: now,
+            cap: None,
+            next_step: now,
+        })
+    }
+
+    /// Takes new timing settings. The set of devices only changes on restart,
+    /// since they cannot be opened once root is gone.
+    pub fn reconfigure(&mut self, config: &IdleConfig) {
+        self.config = config.clone();
+    }
+
+    pub fn fds(&self) -> impl Iterator<Item = BorrowedFd<'_>> {
+        self.devices.iter().map(|d| d.file.as_fd())
+    }
+
+    /// Drains the device at `index` (in [`fds`] order) and reports whether
+    /// it saw any input. A device that went away is dropped.
+    ///
+    /// [`fds`]: Idle::fds
+    pub fn read(&mut self, index: usize) -> bool {
+        let mut seen = false;
+        let mut buf = [0u8; EVENT_SIZE * 64];
+        loop {
+            match self.devices[index].file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    seen |= input::events(&buf[..n])
+                        .any(|(kind, _, _)| matches!(kind, EV_KEY | EV_REL | EV_ABS));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return seen,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    warning!("{}: {e}, no longer watching it", self.devices[index].name);
+                    break;
+                }
+            }
+        }
+        self.devices.remove(index);
+        seen
+    }
+
+    /// Records input; returns whether this lifted an idle limit.
+    pub fn activity(&mut self) -> bool {
+        self.last_activity = Instant::now();
+        let lifted = self.cap.take().is_some();
+        if lifted {
+            debug!("keyboard in use again, lifting the idle limit");
+        }
+        lifted
+    }
+
+    /// Current brightness limit, if idle.
+    pub fn cap(&self) -> Option<u8> {
+        self.cap
+    }
+
+    /// When [`tick`] next has something to do, if ever.
+    ///
+    /// [`tick`]: Idle::tick
+    pub fn deadline(&self) -> Option<Instant> {
+        let timeout = self.config.timeout?;
+        match self.cap {
+            None => Some(self.last_activity + timeout),
+            Some(cap) if cap > self.config.brightness => Some(self.next_step),
+            Some(_) => None,
+        }
+    }
+
+    /// Starts or continues dimming from `brightness` once the timeout has
+    /// passed. Returns whether the limit changed.
+    pub fn tick(&mut self, brightness: u8) -> bool {
+        let Some(timeout) = self.config.timeout else {
+            return self.cap.take().is_some();
+        };
+        let now = Instant::now();
+        if now < self.last_activity + timeout || now < self.next_step {
+            return false;
+        }
+        let target = self.config.brightness;
+        if self.cap.is_none() {
+            debug!("keyboard idle for {timeout:?}, dimming to {target}");
+        }
+        let current = self.cap.unwrap_or(brightness);
+        if current <= target || self.config.fade.is_zero() {
+            let changed = self.cap != Some(target);
+            self.cap = Some(target);
+            return changed;
+        }
+        // One level per step, spread over the fade time.
+        let steps = brightness.saturating_sub(target).max(1);
+        self.cap = Some(current - 1);
+        self.next_step = now + self.config.fade / u32::from(steps);
+        true
+    }
+}
+
+/// Finds the event devices to watch: the i8042 keyboard, plus touchpads
+/// and other keyboards if configured.
+fn discover(config: &IdleConfig) -> Vec<Device> {
+    let mut devices = Vec::new();
+    for input in input::devices() {
+        let wanted = if input.properties & PROP_POINTER != 0 {
+            config.touchpad
+        } else if input.ev & EV_REP_BIT != 0 {
+            input.bus == BUS_I8042 || config.external_keyboards
+        } else {
+            false
+        };
+        if !wanted {
+            continue;
+        }
+        match input.open() {
+            Ok(file) => devices.push(Device {
+                file,
+                name: input.name,
+            }),
+            Err(e) => warning!("cannot open {} ({}): {e}", input.node.display(), input.name),
+        }
+    }
+    devices
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Discovery of evdev devices through sysfs and decoding of their events.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+const INPUT_CLASS: &str = "/sys/class/input";
+
+/// `struct input_event` on 64-bit: a timeval, then type, code and value.
+pub const EVENT_SIZE: usize = 24;
+
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_SW: u16 = 0x05;
+/// `EV_REP` bit in `capabilities/ev`; only keyboards auto-repeat.
+pub const EV_REP_BIT: u64 = 1 << 0x14;
+pub const SW_LID: u16 = 0x00;
+pub const BUS_I8042: u16 = 0x11;
+/// `INPUT_PROP_POINTER`, set for touchpads.
+pub const PROP_POINTER: u64 = 1 << 0;
+
+/// An event device as described by sysfs.
+pub struct InputDevice {
+    pub node: PathBuf,
+    pub name: String,
+    pub bus: u16,
+    pub properties: u64,
+    /// Supported event types.
+    pub ev: u64,
+    /// Supported switches.
+    pub sw: u64,
+}
+
+impl InputDevice {
+    /// Opens the device node for non-blocking reads.
+    pub fn open(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(&self.node)
+    }
+}
+
+/// Lists the event devices known to the kernel.
+pub fn devices() -> Vec<InputDevice> {
+    let Ok(entries) = fs::read_dir(INPUT_CLASS) else {
+        return Vec::new();
+    };
+    let mut devices: Vec<InputDevice> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let node = entry.file_name().into_string().ok()?;
+            if !node.starts_with("event") {
+                return None;
+            }
+            let sys = entry.path().join("device");
+            Some(InputDevice {
+                name: read_trimmed(&sys.join("name")).unwrap_or_else(|| node.clone()),
+                bus: read_trimmed(&sys.join("id/bustype"))
+                    .and_then(|v| u16::from_str_radix(&v, 16).ok())
+                    .unwrap_or(0),
+                properties: hex_bits(&sys.join("properties")),
+                ev: hex_bits(&sys.join("capabilities/ev")),
+                sw: hex_bits(&sys.join("capabilities/sw")),
+                node: Path::new("/dev/input").join(node),
+            })
+        })
+        .collect();
+    devices.sort_by(|a, b| a.node.cmp(&b.node));
+    devices
+}
+
+/// Splits a buffer read from an event device into (type, code, value).
+pub fn events(buf: &[u8]) -> impl Iterator<Item = (u16, u16, i32)> + '_ {
+    buf.chunks_exact(EVENT_SIZE).map(|e| {
+        (
+            u16::from_ne_bytes([e[16], e[17]]),
+            u16::from_ne_bytes([e[18], e[19]]),
+            i32::from_ne_bytes([e[20], e[21], e[22], e[23]]),
+        )
+    })
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Lowest 64 bits of a sysfs bitmap, which is hex words with the most
+/// significant first.
+fn hex_bits(path: &Path) -> u64 {
+    read_trimmed(path)
+        .and_then(|v| {
+            v.split_whitespace()
+                .next_back()
+                .and_then(|w| u64::from_str_radix(w, 16).ok())
+        })
+        .unwrap_or(0)
+}
@@ -0,0 +1,163 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Lid switch state.
+//!
+//! The ACPI lid shows up as an event device reporting `SW_LID`; it is
+//! opened before the daemon drops root and its current position read with
+//! `EVIOCGSW`. Machines without one may still expose the ACPI button under
+//! /proc, which is then re-read about once a second.
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::input::{self, EV_SW, EVENT_SIZE, SW_LID};
+use crate::log::{info, warning};
+
+const PROC_LID: &str = "/proc/acpi/button/lid";
+const PROC_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Source {
+    Evdev { file: File, name: String },
+    Proc { path: PathBuf, checked: Instant },
+    Gone,
+}
+
+pub struct Lid {
+    source: Source,
+    closed: bool,
+}
+
+impl Lid {
+    /// Finds the lid switch. Returns `None` on machines without one.
+    pub fn open() -> Option<Self> {
+        let device = input::devices()
+            .into_iter()
+            .find(|d| d.ev & (1 << EV_SW) != 0 && d.sw & (1 << SW_LID) != 0);
+        if let Some(device) = device {
+            match device.open() {
+                Ok(file) => {
+                    info!("watching {} for the lid", device.name);
+                    let closed = switch_state(&file).unwrap_or(false);
+                    return Some(Lid {
+                        source: Source::Evdev {
+                            file,
+                            name: device.name,
+                        },
+                        closed,
+                    });
+                }
+                Err(e) => {
+                    warning!(
+                        "cannot open {} ({}): {e}",
+                        device.node.display(),
+                        device.name
+                    )
+                }
+            }
+        }
+        let path = fs::read_dir(PROC_LID)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.path().join("state"))
+            .find(|path| path.exists())?;
+        let closed = proc_state(&path)?;
+        info!("polling {} for the lid", path.display());
+        Some(Lid {
+            source: Source::Proc {
+                path,
+                checked: Instant::now(),
+            },
+            closed,
+        })
+    }
+
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+
+    /// The event device to poll, unless the state comes from /proc.
+    pub fn fd(&self) -> Option<BorrowedFd<'_>> {
+        match &self.source {
+            Source::Evdev { file, .. } => Some(file.as_fd()),
+            _ => None,
+        }
+    }
+
+    /// Picks up lid movement: drains the event device when `ready`, or
+    /// re-reads /proc when due. Returns whether the lid opened or closed.
+    pub fn update(&mut self, ready: bool) -> bool {
+        let was = self.closed;
+        match &mut self.source {
+            Source::Evdev { file, name } if ready => {
+                let mut buf = [0u8; EVENT_SIZE * 16];
+                loop {
+                    match file.read(&mut buf) {
+                        Ok(0) => {
+                            warning!("{name} went away, no longer watching the lid");
+                            break;
+                        }
+                        Ok(n) => {
+                            for (kind, code, value) in input::events(&buf[..n]) {
+                                if kind == EV_SW && code == SW_LID {
+                                    self.closed = value != 0;
+                                }
+                            }
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => return self.closed != was,
+                        Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                        Err(e) => {
+                            warning!("{name}: {e}, no longer watching the lid");
+                            break;
+                        }
+                    }
+                }
+                // Without the switch, never keep the backlight off.
+                self.source = Source::Gone;
+                self.closed = false;
+            }
+            Source::Proc { path, checked } if checked.elapsed() >= PROC_INTERVAL => {
+                *checked = Instant::now();
+                if let Some(closed) = proc_state(path) {
+                    self.closed = closed;
+                }
+            }
+            _ => {}
+        }
+        self.closed != was
+    }
+}
+
+/// Reads the current switch bits with `EVIOCGSW`.
+fn switch_state(file: &File) -> Option<bool> {
+    let mut bits = [0u8; 8];
+    // _IOC(_IOC_READ, 'E', 0x1b, len)
+    let request = (2 << 30) | ((bits.len() as u64) << 16) | (0x45 << 8) | 0x1b;
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, bits.as_mut_ptr()) };
+    (ret >= 0).then(|| bits[0] & (1 << SW_LID) != 0)
+}
+
+/// Parses `state:      closed` from the ACPI button file.
+fn proc_state(path: &Path) -> Option<bool> {
+    let text = fs::read_to_string(path).ok()?;
+    match text.split_whitespace().nth(1)? {
+        "closed" => Some(true),
+        "open" => Some(false),
+        _ => None,
+    }
+}
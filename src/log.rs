@@ -0,0 +1,245 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Leveled, structured daemon logging.
+//!
+//! Messages carry a level and optional `key = value` fields:
+//!
+//! ```ignore
+//! warning!(peer_uid = peer.uid, error_code = "denied"; "{verb} refused");
+//! info!("listening on {}", path.display());
+//! ```
+//!
+//! They go to stderr (`daemon: ` prefix, fields appended), syslog, or the
+//! systemd journal's native protocol, which keeps the fields as journal
+//! fields. Messages above the current level are dropped before formatting.
+
+use std::fmt;
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Path of the local syslog socket.
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// Native journal protocol socket.
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+/// syslog facility `daemon`, already shifted into place.
+const FACILITY_DAEMON: u8 = 3 << 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warning,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const NAMES: &[&str] = &["error", "warning", "info", "debug", "trace"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Level::Error),
+            "warning" | "warn" => Some(Level::Warning),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    /// syslog severity; the journal uses the same numbers for PRIORITY.
+    fn severity(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warning => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Level::Error,
+            2 => Level::Warning,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(Level::NAMES[*self as usize - 1])
+    }
+}
+
+/// Where log messages go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Stderr,
+    Syslog,
+    Journal,
+}
+
+enum Sink {
+    Stderr,
+    Syslog(UnixDatagram),
+    Journal(UnixDatagram),
+}
+
+static SINK: Mutex<Sink> = Mutex::new(Sink::Stderr);
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn level() -> Level {
+    Level::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level <= self::level()
+}
+
+/// Whether stderr is already connected to the journal (systemd sets
+/// `JOURNAL_STREAM` for services), so native output is preferable.
+pub fn under_journal() -> bool {
+    std::env::var_os("JOURNAL_STREAM").is_some()
+}
+
+/// Switches to `target`. Sockets are connected right away so logging keeps
+/// working inside the sandbox; if that fails, stderr stays in use.
+pub fn init(target: Target) {
+    let (path, wrap): (&str, fn(UnixDatagram) -> Sink) = match target {
+        Target::Stderr => return,
+        Target::Syslog => (SYSLOG_SOCKET, Sink::Syslog),
+        Target::Journal => (JOURNAL_SOCKET, Sink::Journal),
+    };
+    let socket = UnixDatagram::unbound().and_then(|s| {
+        s.connect(path)?;
+        Ok(s)
+    });
+    match socket {
+        Ok(socket) => *SINK.lock().unwrap_or_else(|e| e.into_inner()) = wrap(socket),
+        Err(e) => write(
+            Level::Warning,
+            &[],
+            format_args!("cannot connect to {path} ({e}), logging to stderr"),
+        ),
+    }
+}
+
+pub fn write(level: Level, fields: &[(&str, &dyn fmt::Display)], args: fmt::Arguments<'_>) {
+    let sink = SINK.lock().unwrap_or_else(|e| e.into_inner());
+    match &*sink {
+        Sink::Stderr => {
+            let mut line = String::from("daemon: ");
+            if level != Level::Info {
+                line.push_str(&format!("{level}: "));
+            }
+            line.push_str(&plain(fields, args));
+            line.push('\n');
+            // One write per message, so lines from different sources do not interleave.
+            let _ = std::io::stderr().write_all(line.as_bytes());
+        }
+        Sink::Syslog(socket) => {
+            let message = format!(
+                "<{}>{}[{}]: {}",
+                FACILITY_DAEMON | level.severity(),
+                env!("CARGO_PKG_NAME"),
+                std::process::id(),
+                plain(fields, args)
+            );
+            let _ = socket.send(message.as_bytes());
+        }
+        Sink::Journal(socket) => {
+            let mut datagram = Vec::new();
+            journal_field(&mut datagram, "MESSAGE", &args.to_string());
+            journal_field(&mut datagram, "PRIORITY", &level.severity().to_string());
+            journal_field(&mut datagram, "SYSLOG_IDENTIFIER", env!("CARGO_PKG_NAME"));
+            for (key, value) in fields {
+                journal_field(&mut datagram, &key.to_ascii_uppercase(), &value.to_string());
+            }
+            let _ = socket.send(&datagram);
+        }
+    }
+}
+
+/// Message with fields appended as `key=value`, for line-oriented sinks.
+fn plain(fields: &[(&str, &dyn fmt::Display)], args: fmt::Arguments<'_>) -> String {
+    let mut text = args.to_string();
+    for (key, value) in fields {
+        text.push_str(&format!(" {key}={value}"));
+    }
+    text
+}
+
+/// Appends one field in the journal's native format. Values containing a
+/// newline use the length-prefixed binary form.
+fn journal_field(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        out.push(b'\n');
+        out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        out.push(b'=');
+    }
+    out.extend_from_slice(value.as_bytes());
+    out.push(b'\n');
+}
+
+macro_rules! log_at {
+    ($level:expr, $($key:ident = $value:expr),+ ; $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write(
+                $level,
+                &[$((stringify!($key), &$value as &dyn ::std::fmt::Display)),+],
+                format_args!($($arg)+),
+            )
+        }
+    };
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, &[], format_args!($($arg)+))
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Error, $($arg)+) };
+}
+
+macro_rules! warning {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Warning, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Debug, $($arg)+) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Trace, $($arg)+) };
+}
+
+pub(crate) use {debug, error, info, log_at, trace, warning};
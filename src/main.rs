@@ -13,34 +13,213 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod als;
+mod audit;
 mod cli;
 mod client;
+mod config;
 mod daemon;
+mod dbus;
+mod detach;
 mod ec;
 mod error;
+mod idle;
+mod input;
+mod json;
+mod lid;
+mod log;
+mod policy;
+mod polkit;
+mod power;
 mod protocol;
+mod ratelimit;
+mod rules;
+mod runtime;
+mod sandbox;
+mod schedule;
+mod sleep;
+mod stats;
+mod systemd;
+mod tz;
+mod uevent;
+
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
 
-use cli::{Cli, Command};
+use cli::{Cli, Command, DaemonAction, LogTarget, ScheduleAction};
+use config::Config;
 use error::Error;
-use protocol::Request;
+use protocol::{Request, ScheduleCommand};
 
 fn main() {
     let cli = Cli::parse();
 
-    if let Err(e) = run(cli.command) {
+    if let Command::Daemon {
+        action,
+        config,
+        detach,
+        log,
+        log_level,
+        ..
+    } = &cli.command
+    {
+        let result = match action {
+            Some(action) => daemon_control(*action, config, cli.socket.clone()),
+            None => run_daemon(
+                config,
+                cli.socket.as_deref(),
+                cli.group.as_deref(),
+                *detach,
+                *log,
+                log_level.as_deref(),
+            ),
+        };
+        if let Err(e) = result {
+            eprintln!("error: {e}");
+            std::process::exit(e.exit_status());
+        }
+        return;
+    }
+    if let Command::PrintUnits { config } = &cli.command {
+        if let Err(e) = print_units(config, cli.socket, cli.group) {
+            eprintln!("error: {e}");
+            std::process::exit(e.exit_status());
+        }
+        return;
+    }
+
+    // Clients follow the daemon's config file unless told otherwise; an
+    // unreadable file just means the built-in defaults.
+    let defaults = Config::load(Path::new(config::DEFAULT_PATH))
+        .map(|c| c.daemon)
+        .unwrap_or_default();
+    let socket = cli.socket.unwrap_or(defaults.socket);
+    let group = cli.group.unwrap_or(defaults.group);
+
+    if let Err(e) = run_client(cli.command, &socket) {
         eprintln!("error: {e}");
-        std::process::exit(1);
+        if matches!(e, Error::Permission) {
+            eprintln!(
+                "hint: {} is only accessible to members of the {group} group",
+                socket.display()
+            );
+        }
+        std::process::exit(e.exit_status());
+    }
+}
+
+fn run_daemon(
+    config: &Path,
+    socket: Option<&Path>,
+    group: Option<&str>,
+    detach: bool,
+    log: Option<LogTarget>,
+    log_level: Option<&str>,
+) -> error::Result<()> {
+    let log_level = log_level
+        .map(|name| {
+            log::Level::parse(name).ok_or_else(|| {
+                Error::Config(format!(
+                    "unknown log level {name} (expected one of: {})",
+                    log::Level::NAMES.join(", ")
+                ))
+            })
+        })
+        .transpose()?;
+    let mut startup = if detach {
+        Some(detach::detach()?)
+    } else {
+        None
+    };
+    let target = match log {
+        Some(LogTarget::Stderr) => log::Target::Stderr,
+        Some(LogTarget::Syslog) => log::Target::Syslog,
+        Some(LogTarget::Journal) => log::Target::Journal,
+        None if detach => log::Target::Syslog,
+        None if log::under_journal() => log::Target::Journal,
+        None => log::Target::Stderr,
+    };
+    log::init(target);
+
+    let result = daemon::Daemon::run(config, socket, group, log_level, &mut startup);
+    if let Err(e) = &result {
+        if target != log::Target::Stderr {
+            log::error!(error_code = e.code().as_str(); "{e}");
+        }
+        if let Some(startup) = startup {
+            startup.failed(e);
+        }
+    }
+    result
+}
+
+/// `daemon stop` and `daemon status`, for inits without a service manager.
+fn daemon_control(
+    action: DaemonAction,
+    config: &Path,
+    socket: Option<PathBuf>,
+) -> error::Result<()> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => Config::load(config)?.daemon.socket,
+    };
+    match action {
+        DaemonAction::Stop => detach::stop(&socket),
+        DaemonAction::Status => match detach::running(&socket)? {
+            Some(pid) => {
+                println!("running (pid {pid})");
+                Ok(())
+            }
+            None => {
+                println!("not running");
+                std::process::exit(Error::DaemonNotRunning.exit_status());
+            }
+        },
     }
 }
 
-fn run(command: Command) -> error::Result<()> {
+fn print_units(
+    config_path: &Path,
+    socket: Option<PathBuf>,
+    group: Option<String>,
+) -> error::Result<()> {
+    let mut config = Config::load(config_path)?;
+    if let Some(socket) = socket {
+        config.daemon.socket = socket;
+    }
+    if let Some(group) = group {
+        config.daemon.group = group;
+    }
+    let exe = std::env::current_exe()?;
+    for (name, unit) in systemd::units(&config, config_path, &exe) {
+        println!("# /etc/systemd/system/{name}");
+        println!("{unit}");
+    }
+    Ok(())
+}
+
+fn run_client(command: Command, socket: &Path) -> error::Result<()> {
     match command {
-        Command::Daemon => daemon::Daemon::run(),
+        Command::Capabilities => {
+            let caps = client::query_capabilities(socket)?;
+            println!("protocol: {}", caps.protocol);
+            println!("daemon:   {}", caps.version);
+            println!("backend:  {}", caps.backend);
+            println!("model:    {}", caps.model);
+            println!("zones:    {}", caps.zones);
+            println!("verbs:    {}", caps.verbs.join(" "));
+            Ok(())
+        }
+        Command::Subscribe => client::subscribe(socket),
+        Command::History { count } => client::history(socket, count),
+        Command::Stats => client::stats(socket),
+        Command::Schedule {
+            action: None | Some(ScheduleAction::List),
+        } => client::schedules(socket),
         cmd => {
             let request = command_to_request(cmd)?;
-            client::send_command(&request)
+            client::send_command(socket, &request)
         }
     }
 }
@@ -70,6 +249,52 @@ fn command_to_request(cmd: Command) -> error::Result<Request> {
             }
             Ok(Request::AdjustBrightness(delta))
         }
-        Command::Daemon => unreachable!(),
+        Command::Set { brightness, color } => {
+            if brightness.is_none() && color.is_none() {
+                return Err(Error::Protocol(
+                    "set needs --brightness and/or --color".into(),
+                ));
+            }
+            if let Some(level) = brightness
+                && level > 9
+            {
+                return Err(Error::InvalidBrightness(level));
+            }
+            let color = color
+                .as_deref()
+                .map(protocol::parse_hex_color)
+                .transpose()?;
+            Ok(Request::Set { brightness, color })
+        }
+        Command::Status => Ok(Request::Status),
+        Command::Reload => Ok(Request::Reload),
+        Command::LogLevel { level } => Ok(Request::LogLevel(
+            level
+                .map(|name| {
+                    log::Level::parse(&name).ok_or_else(|| {
+                        Error::Protocol(format!(
+                            "unknown log level: {name} (expected one of: {})",
+                            log::Level::NAMES.join(", ")
+                        ))
+                    })
+                })
+                .transpose()?,
+        )),
+        Command::Schedule {
+            action: Some(ScheduleAction::Enable { name }),
+        } => Ok(Request::Schedule(ScheduleCommand::Enable(name))),
+        Command::Schedule {
+            action: Some(ScheduleAction::Disable { name }),
+        } => Ok(Request::Schedule(ScheduleCommand::Disable(name))),
+        Command::Schedule { action: None }
+        | Command::Schedule {
+            action: Some(ScheduleAction::List),
+        }
+        | Command::Daemon { .. }
+        | Command::PrintUnits { .. }
+        | Command::Capabilities
+        | Command::Subscribe
+        | Command::History { .. }
+        | Command::Stats => unreachable!(),
     }
 }
@@ -0,0 +1,322 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-peer authorization based on SO_PEERCRED.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use nix::unistd::{Group, User, getgid, getuid};
+
+use crate::config::{PolicyConfig, Rate};
+use crate::error::{Error, Result};
+use crate::log::warning;
+use crate::polkit::{self, Polkit};
+use crate::protocol::{Access, Request};
+use crate::ratelimit::TokenBucket;
+
+/// Pause between attempts at reaching polkit, so an unreachable bus costs
+/// one connection timeout per interval rather than one per request.
+const POLKIT_RETRY: Duration = Duration::from_secs(10);
+
+/// How long a polkit answer is reused for the same uid and action, so that
+/// a burst of commands costs one synchronous round trip to polkit.
+const POLKIT_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Identity of the process on the other end of a connection.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: i32,
+    pub groups: Vec<u32>,
+    /// Executable path, or the peer's `argv[0]` in brackets when the daemon
+    /// may not read its `/proc/<pid>/exe`.
+    pub exe: String,
+}
+
+impl Peer {
+    pub fn from_stream(stream: &UnixStream) -> Result<Self> {
+        let cred = getsockopt(stream, PeerCredentials).map_err(|e| Error::Io(e.into()))?;
+        let groups = Self::supplementary_groups(cred.pid()).unwrap_or_default();
+        Ok(Peer {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+            groups,
+            exe: Self::executable(cred.pid()),
+        })
+    }
+
+    /// The daemon itself, acting for the feature described by `exe`.
+    pub fn daemon(exe: String) -> Self {
+        Peer {
+            uid: getuid().as_raw(),
+            gid: getgid().as_raw(),
+            pid: std::process::id() as i32,
+            groups: Vec::new(),
+            exe,
+        }
+    }
+
+    /// The result is escaped, since both the path and `argv[0]` are chosen
+    /// by the peer and end up in one-line audit records.
+    fn executable(pid: i32) -> String {
+        if let Ok(path) = fs::read_link(format!("/proc/{pid}/exe")) {
+            return escape(&path.display().to_string());
+        }
+        // Unlike exe, cmdline is world-readable, but the peer controls it.
+        match fs::read(format!("/proc/{pid}/cmdline")) {
+            Ok(cmdline) => {
+                let argv0 = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+                format!("[{}]", escape(&String::from_utf8_lossy(argv0)))
+            }
+            Err(_) => "?".into(),
+        }
+    }
+
+    /// SO_PEERCRED only carries the primary group; the rest comes from
+    /// /proc, which reflects the groups the process actually runs with.
+    fn supplementary_groups(pid: i32) -> Option<Vec<u32>> {
+        let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+        let line = status.lines().find_map(|l| l.strip_prefix("Groups:"))?;
+        Some(
+            line.split_whitespace()
+                .filter_map(|g| g.parse().ok())
+                .collect(),
+        )
+    }
+
+    fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "uid={} gid={} pid={}", self.uid, self.gid, self.pid)
+    }
+}
+
+/// Compiled form of [`PolicyConfig`] with names resolved to ids.
+pub struct Policy {
+    read_only_users: Vec<u32>,
+    read_only_groups: Vec<u32>,
+    admin_users: Vec<u32>,
+    admin_groups: Vec<u32>,
+    rate_limit: Option<Rate>,
+    buckets: HashMap<u32, TokenBucket>,
+    socket_group: Option<u32>,
+    /// `[policy] polkit`; the socket is world-accessible while it is set.
+    use_polkit: bool,
+    polkit: Option<Polkit>,
+    /// Earliest time to try connecting to polkit again after a failure.
+    polkit_retry: Option<Instant>,
+    /// Recent polkit answers by (uid, action), with their expiry.
+    polkit_cache: HashMap<(u32, &'static str), (bool, Instant)>,
+}
+
+impl Policy {
+    /// `socket_group` is the group owning the control socket; its members
+    /// may use write verbs without asking polkit.
+    pub fn new(config: &PolicyConfig, socket_group: Option<u32>) -> Self {
+        let mut policy = Policy {
+            read_only_users: Vec::new(),
+            read_only_groups: Vec::new(),
+            admin_users: Vec::new(),
+            admin_groups: Vec::new(),
+            rate_limit: None,
+            buckets: HashMap::new(),
+            socket_group,
+            use_polkit: false,
+            polkit: None,
+            polkit_retry: None,
+            polkit_cache: HashMap::new(),
+        };
+        policy.reconfigure(config);
+        policy
+    }
+
+    /// Applies a new configuration. An existing polkit connection is kept,
+    /// since it was opened with the daemon's original credentials.
+    pub fn reconfigure(&mut self, config: &PolicyConfig) {
+        self.read_only_users = resolve_users(&config.read_only_users);
+        self.read_only_groups = resolve_groups(&config.read_only_groups);
+        self.admin_users = resolve_users(&config.admin_users);
+        self.admin_groups = resolve_groups(&config.admin_groups);
+        if self.rate_limit != config.rate_limit {
+            self.buckets.clear();
+        }
+        self.rate_limit = config.rate_limit;
+
+        self.use_polkit = config.polkit;
+        self.polkit_cache.clear();
+        if !config.polkit {
+            self.polkit = None;
+        } else if self.polkit.is_none() {
+            self.polkit_retry = None;
+            self.connect_polkit();
+        }
+    }
+
+    fn connect_polkit(&mut self) {
+        if self.polkit_retry.is_some_and(|at| Instant::now() < at) {
+            return;
+        }
+        match Polkit::connect() {
+            Ok(polkit) => {
+                self.polkit = Some(polkit);
+                self.polkit_retry = None;
+            }
+            Err(e) => {
+                warning!("polkit unavailable, only socket group members may write: {e}");
+                self.polkit_retry = Some(Instant::now() + POLKIT_RETRY);
+            }
+        }
+    }
+
+    fn polkit_allows(&mut self, peer: &Peer, action: &'static str) -> bool {
+        if !self.use_polkit {
+            return false;
+        }
+        let now = Instant::now();
+        if let Some(&(allowed, expires)) = self.polkit_cache.get(&(peer.uid, action))
+            && now < expires
+        {
+            return allowed;
+        }
+        if self.polkit.as_ref().is_none_or(Polkit::broken) {
+            self.polkit = None;
+            self.connect_polkit();
+        }
+        let Some(polkit) = self.polkit.as_mut() else {
+            return false;
+        };
+        match polkit.check(peer, action) {
+            Ok(allowed) => {
+                self.polkit_cache
+                    .retain(|_, &mut (_, expires)| now < expires);
+                self.polkit_cache
+                    .insert((peer.uid, action), (allowed, now + POLKIT_CACHE_TTL));
+                allowed
+            }
+            Err(e) => {
+                warning!("polkit check of {action} for {peer} failed: {e}");
+                false
+            }
+        }
+    }
+
+    fn is_admin(&self, peer: &Peer) -> bool {
+        peer.uid == 0
+            || self.admin_users.contains(&peer.uid)
+            || self.admin_groups.iter().any(|&g| peer.in_group(g))
+    }
+
+    fn is_read_only(&self, peer: &Peer) -> bool {
+        self.read_only_users.contains(&peer.uid)
+            || self.read_only_groups.iter().any(|&g| peer.in_group(g))
+    }
+
+    /// Decides whether `peer` may issue `req`, charging its rate budget.
+    pub fn admit(&mut self, peer: &Peer, req: &Request) -> Result<()> {
+        // Charged first, so that a flood of requests cannot turn into a
+        // flood of polkit calls. The handshake is free so that every client
+        // invocation costs one token.
+        if let Some(rate) = self.rate_limit
+            && peer.uid != 0
+            && !matches!(req, Request::Hello { .. })
+            && !self
+                .buckets
+                .entry(peer.uid)
+                .or_insert_with(|| TokenBucket::new(rate))
+                .try_take()
+        {
+            return Err(Error::Throttled(format!(
+                "rate limit exceeded for uid {}",
+                peer.uid
+            )));
+        }
+
+        let allowed = match req.access() {
+            Access::Read => true,
+            Access::Write if self.is_admin(peer) => true,
+            Access::Write if self.is_read_only(peer) => false,
+            // With polkit the socket is world-accessible, so peers outside
+            // the socket group need polkit's blessing (e.g. an active session),
+            // and get none while polkit cannot be reached.
+            Access::Write => {
+                !self.use_polkit
+                    || self.socket_group.is_some_and(|g| peer.in_group(g))
+                    || self.polkit_allows(peer, polkit::ACTION_CONTROL)
+            }
+            Access::Admin => self.is_admin(peer) || self.polkit_allows(peer, polkit::ACTION_ADMIN),
+        };
+        if !allowed {
+            return Err(Error::Denied(format!(
+                "{} not allowed for uid {}",
+                req.verb(),
+                peer.uid
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Replaces backslashes, whitespace and control characters with `\xNN`
+/// escapes of their UTF-8 bytes, so the text stays a single word.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\\' || c.is_whitespace() || c.is_control() {
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                out.push_str(&format!("\\x{byte:02x}"));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn resolve_users(names: &[String]) -> Vec<u32> {
+    names
+        .iter()
+        .filter_map(|name| match User::from_name(name) {
+            Ok(Some(user)) => Some(user.uid.as_raw()),
+            _ => {
+                warning!("policy: unknown user {name}, ignoring");
+                None
+            }
+        })
+        .collect()
+}
+
+fn resolve_groups(names: &[String]) -> Vec<u32> {
+    names
+        .iter()
+        .filter_map(|name| match Group::from_name(name) {
+            Ok(Some(group)) => Some(group.gid.as_raw()),
+            _ => {
+                warning!("policy: unknown group {name}, ignoring");
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,102 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! polkit authorization checks for connecting peers.
+
+use std::fs;
+use std::time::Duration;
+
+use crate::dbus::{self, Arg};
+use crate::error::{Error, Result};
+use crate::policy::Peer;
+
+/// Ordinary backlight changes; granted to active local sessions by default.
+pub const ACTION_CONTROL: &str = "org.gigabyte-kbd-backlight.control";
+/// Daemon administration such as reloading the configuration.
+pub const ACTION_ADMIN: &str = "org.gigabyte-kbd-backlight.admin";
+
+/// Longest the daemon waits for polkit. Checks run on the event loop, so
+/// every other client waits as well.
+const CALL_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub struct Polkit {
+    bus: dbus::Connection,
+    /// A call failed, so the connection may be out of step with the bus.
+    broken: bool,
+}
+
+impl Polkit {
+    pub fn connect() -> Result<Self> {
+        Ok(Polkit {
+            bus: dbus::Connection::system_with_timeout(CALL_TIMEOUT)?,
+            broken: false,
+        })
+    }
+
+    /// Whether the connection should be replaced before the next check.
+    pub fn broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Asks polkit whether `peer` may perform `action`. No interactive
+    /// authentication is attempted: the daemon cannot block on a password
+    /// prompt, so a challenge counts as a refusal.
+    pub fn check(&mut self, peer: &Peer, action: &str) -> Result<bool> {
+        let start_time = process_start_time(peer.pid)?;
+        let subject = Arg::Struct(vec![
+            Arg::Str("unix-process".into()),
+            Arg::dict(vec![
+                ("pid", Arg::U32(peer.pid as u32)),
+                ("start-time", Arg::U64(start_time)),
+                ("uid", Arg::I32(peer.uid as i32)),
+            ]),
+        ]);
+
+        let reply = self.bus.call(
+            "org.freedesktop.PolicyKit1",
+            "/org/freedesktop/PolicyKit1/Authority",
+            "org.freedesktop.PolicyKit1.Authority",
+            "CheckAuthorization",
+            &[
+                subject,
+                Arg::Str(action.into()),
+                Arg::Array("{ss}".into(), Vec::new()),
+                Arg::U32(0),
+                Arg::Str(String::new()),
+            ],
+        );
+        self.broken = reply.is_err();
+        let reply = reply?;
+
+        reply
+            .args()?
+            .first()
+            .and_then(Arg::fields)
+            .and_then(|f| f.first())
+            .and_then(Arg::as_bool)
+            .ok_or_else(|| Error::Dbus("unexpected CheckAuthorization reply".into()))
+    }
+}
+
+/// Process start time in clock ticks since boot (field 22 of
+/// /proc/<pid>/stat), which polkit uses to guard against PID reuse.
+fn process_start_time(pid: i32) -> Result<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat"))?;
+    // The command name may contain spaces, so count fields after its ')'.
+    stat.rsplit_once(')')
+        .and_then(|(_, rest)| rest.split_whitespace().nth(19))
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| Error::Protocol(format!("cannot read start time of pid {pid}")))
+}
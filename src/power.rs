@@ -0,0 +1,86 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! AC and battery state from /sys/class/power_supply.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerState {
+    pub on_ac: bool,
+    /// Mean charge of the system batteries, in percent.
+    pub capacity: Option<u8>,
+}
+
+impl PowerState {
+    /// Reads the current state. Returns `None` on machines without a
+    /// system battery, where there is nothing to react to.
+    pub fn read() -> Option<Self> {
+        let mut mains = None;
+        let mut discharging = false;
+        let mut capacities = Vec::new();
+        let mut batteries = 0;
+        for entry in fs::read_dir(POWER_SUPPLY).ok()?.flatten() {
+            let dir = entry.path();
+            match read_trimmed(&dir.join("type")).as_deref() {
+                Some("Mains") => {
+                    let online = read_trimmed(&dir.join("online")).as_deref() == Some("1");
+                    mains = Some(mains.unwrap_or(false) || online);
+                }
+                // Peripherals such as mice report their batteries with
+                // scope Device.
+                Some("Battery")
+                    if read_trimmed(&dir.join("scope")).as_deref() != Some("Device") =>
+                {
+                    batteries += 1;
+                    discharging |=
+                        read_trimmed(&dir.join("status")).as_deref() == Some("Discharging");
+                    capacities.extend(
+                        read_trimmed(&dir.join("capacity")).and_then(|c| c.parse::<u32>().ok()),
+                    );
+                }
+                _ => {}
+            }
+        }
+        if batteries == 0 {
+            return None;
+        }
+        let capacity = (!capacities.is_empty())
+            .then(|| (capacities.iter().sum::<u32>() / capacities.len() as u32).min(100) as u8);
+        Some(PowerState {
+            // Without a Mains supply, go by what the battery says.
+            on_ac: mains.unwrap_or(!discharging),
+            capacity,
+        })
+    }
+}
+
+impl fmt::Display for PowerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.on_ac { "on AC" } else { "on battery" })?;
+        if let Some(capacity) = self.capacity {
+            write!(f, ", {capacity}%")?;
+        }
+        Ok(())
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
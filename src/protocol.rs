@@ -13,18 +13,104 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::fmt;
+
 use crate::error::Error;
+use crate::json::Value;
+use crate::log::Level;
 
-pub const SOCKET_PATH: &str = "/run/kbdlight.sock";
+pub const SOCKET_PATH: &str = "/run/kbdlight/kbdlight.sock";
 pub const GROUP_NAME: &str = "kbdlight";
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Verbs understood by this daemon, advertised in the HELLO reply.
+pub const VERBS: &[&str] = &[
+    "HELLO",
+    "ON",
+    "OFF",
+    "COLOR",
+    "BRIGHTNESS",
+    "ADJUST",
+    "SET",
+    "STATUS",
+    "SUBSCRIBE",
+    "RELOAD",
+    "HISTORY",
+    "LOGLEVEL",
+    "STATS",
+    "SCHEDULE",
+];
+
+/// Audit entries returned by `HISTORY` when no count is given.
+pub const HISTORY_DEFAULT: usize = 20;
+
+/// Privilege class of a request, checked against the peer's credentials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Queries that do not change anything.
+    Read,
+    /// Ordinary backlight changes.
+    Write,
+    /// Daemon administration.
+    Admin,
+}
+
+/// Wire format of a connection. Text is the legacy line protocol; JSON-lines
+/// is selected by a first byte of `{` or by `HELLO <version> JSON`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Text,
+    Json,
+}
+
+impl Framing {
+    pub fn detect(line: &str) -> Self {
+        if line.trim_start().starts_with('{') {
+            Framing::Json
+        } else {
+            Framing::Text
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Request {
+    Hello {
+        version: u32,
+        json: bool,
+    },
     On,
     Off,
-    SetColor { r: u8, g: u8, b: u8 },
+    SetColor {
+        r: u8,
+        g: u8,
+        b: u8,
+    },
     Brightness(u8),
     AdjustBrightness(i8),
+    /// Compound update applied as a single transaction.
+    Set {
+        brightness: Option<u8>,
+        color: Option<(u8, u8, u8)>,
+    },
+    Status,
+    Subscribe,
+    Reload,
+    /// Most recent audit log entries.
+    History(usize),
+    /// Queries the daemon's log level, or changes it.
+    LogLevel(Option<Level>),
+    /// Daemon statistics.
+    Stats,
+    /// Lists the schedules, or pauses or resumes one.
+    Schedule(ScheduleCommand),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleCommand {
+    List,
+    Enable(String),
+    Disable(String),
 }
 
 impl Request {
@@ -36,6 +122,21 @@ impl Request {
             .ok_or_else(|| Error::Protocol("empty command".into()))?;
 
         match cmd {
+            "HELLO" => {
+                let version: u32 = parts
+                    .next()
+                    .ok_or_else(|| Error::Protocol("missing version".into()))?
+                    .parse()
+                    .map_err(|_| Error::Protocol("invalid version".into()))?;
+                let json = match parts.next() {
+                    None | Some("TEXT") => false,
+                    Some("JSON") => true,
+                    Some(other) => {
+                        return Err(Error::Protocol(format!("unknown framing: {other}")));
+                    }
+                };
+                Ok(Request::Hello { version, json })
+            }
             "ON" => Ok(Request::On),
             "OFF" => Ok(Request::Off),
             "COLOR" => {
@@ -62,7 +163,7 @@ impl Request {
                     .ok_or_else(|| Error::Protocol("missing level".into()))?
                     .parse()
                     .map_err(|_| Error::Protocol("invalid level".into()))?;
-                Ok(Request::Brightness(level))
+                Ok(Request::Brightness(check_level(level)?))
             }
             "ADJUST" => {
                 let delta: i8 = parts
@@ -72,24 +173,481 @@ impl Request {
                     .map_err(|_| Error::Protocol("invalid delta".into()))?;
                 Ok(Request::AdjustBrightness(delta))
             }
+            "SET" => {
+                let mut brightness = None;
+                let mut color = None;
+                for field in parts {
+                    match field.split_once('=') {
+                        Some(("brightness", v)) => {
+                            let level = v
+                                .parse()
+                                .map_err(|_| Error::Protocol("invalid brightness".into()))?;
+                            brightness = Some(check_level(level)?);
+                        }
+                        Some(("color", v)) => color = Some(parse_hex_color(v)?),
+                        _ => return Err(Error::Protocol(format!("unknown SET field: {field}"))),
+                    }
+                }
+                Ok(Request::Set { brightness, color })
+            }
+            "STATUS" => Ok(Request::Status),
+            "SUBSCRIBE" => Ok(Request::Subscribe),
+            "RELOAD" => Ok(Request::Reload),
+            "HISTORY" => match parts.next() {
+                Some(count) => Ok(Request::History(
+                    count
+                        .parse()
+                        .map_err(|_| Error::Protocol("invalid count".into()))?,
+                )),
+                None => Ok(Request::History(HISTORY_DEFAULT)),
+            },
+            "LOGLEVEL" => match parts.next() {
+                Some(level) => Ok(Request::LogLevel(Some(parse_level(level)?))),
+                None => Ok(Request::LogLevel(None)),
+            },
+            "STATS" => Ok(Request::Stats),
+            "SCHEDULE" => {
+                let command = match (parts.next(), parts.next()) {
+                    (None | Some("LIST"), None) => ScheduleCommand::List,
+                    (Some("ENABLE"), Some(name)) => ScheduleCommand::Enable(name.into()),
+                    (Some("DISABLE"), Some(name)) => ScheduleCommand::Disable(name.into()),
+                    _ => {
+                        return Err(Error::Protocol(
+                            "expected SCHEDULE [LIST], SCHEDULE ENABLE <name> or SCHEDULE DISABLE <name>"
+                                .into(),
+                        ));
+                    }
+                };
+                Ok(Request::Schedule(command))
+            }
             _ => Err(Error::Protocol(format!("unknown command: {cmd}"))),
         }
     }
 
     pub fn to_line(&self) -> String {
         match self {
+            Request::Hello {
+                version,
+                json: false,
+            } => format!("HELLO {version}"),
+            Request::Hello {
+                version,
+                json: true,
+            } => format!("HELLO {version} JSON"),
             Request::On => "ON".into(),
             Request::Off => "OFF".into(),
             Request::SetColor { r, g, b } => format!("COLOR {r} {g} {b}"),
             Request::Brightness(level) => format!("BRIGHTNESS {level}"),
             Request::AdjustBrightness(delta) => format!("ADJUST {delta}"),
+            Request::Set { brightness, color } => {
+                let mut line = String::from("SET");
+                if let Some(level) = brightness {
+                    line.push_str(&format!(" brightness={level}"));
+                }
+                if let Some((r, g, b)) = color {
+                    line.push_str(&format!(" color={r:02X}{g:02X}{b:02X}"));
+                }
+                line
+            }
+            Request::Status => "STATUS".into(),
+            Request::Subscribe => "SUBSCRIBE".into(),
+            Request::Reload => "RELOAD".into(),
+            Request::History(count) => format!("HISTORY {count}"),
+            Request::LogLevel(None) => "LOGLEVEL".into(),
+            Request::LogLevel(Some(level)) => format!("LOGLEVEL {level}"),
+            Request::Stats => "STATS".into(),
+            Request::Schedule(ScheduleCommand::List) => "SCHEDULE".into(),
+            Request::Schedule(ScheduleCommand::Enable(name)) => format!("SCHEDULE ENABLE {name}"),
+            Request::Schedule(ScheduleCommand::Disable(name)) => {
+                format!("SCHEDULE DISABLE {name}")
+            }
+        }
+    }
+
+    /// Protocol verb, as used in logs and policy messages.
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Request::Hello { .. } => "HELLO",
+            Request::On => "ON",
+            Request::Off => "OFF",
+            Request::SetColor { .. } => "COLOR",
+            Request::Brightness(_) => "BRIGHTNESS",
+            Request::AdjustBrightness(_) => "ADJUST",
+            Request::Set { .. } => "SET",
+            Request::Status => "STATUS",
+            Request::Subscribe => "SUBSCRIBE",
+            Request::Reload => "RELOAD",
+            Request::History(_) => "HISTORY",
+            Request::LogLevel(_) => "LOGLEVEL",
+            Request::Stats => "STATS",
+            Request::Schedule(_) => "SCHEDULE",
+        }
+    }
+
+    pub fn access(&self) -> Access {
+        match self {
+            Request::Hello { .. }
+            | Request::Status
+            | Request::Subscribe
+            | Request::History(_)
+            | Request::LogLevel(None)
+            | Request::Stats
+            | Request::Schedule(ScheduleCommand::List) => Access::Read,
+            Request::On
+            | Request::Off
+            | Request::SetColor { .. }
+            | Request::Brightness(_)
+            | Request::AdjustBrightness(_)
+            | Request::Set { .. } => Access::Write,
+            Request::Reload | Request::LogLevel(Some(_)) | Request::Schedule(_) => Access::Admin,
+        }
+    }
+
+    /// Whether the request changes backlight state.
+    pub fn mutates(&self) -> bool {
+        self.access() == Access::Write
+    }
+
+    /// Parses a JSON-lines request such as `{"cmd":"brightness","level":5}`.
+    pub fn from_json(value: &Value) -> Result<Self, Error> {
+        let cmd = value
+            .get("cmd")
+            .and_then(Value::as_str)
+            .ok_or_else(|| Error::Protocol("missing \"cmd\"".into()))?;
+
+        match cmd {
+            "hello" => Ok(Request::Hello {
+                version: json_int(value, "version")?,
+                json: true,
+            }),
+            "on" => Ok(Request::On),
+            "off" => Ok(Request::Off),
+            "color" => match value.get("color").and_then(Value::as_str) {
+                Some(hex) => {
+                    let (r, g, b) = parse_hex_color(hex)?;
+                    Ok(Request::SetColor { r, g, b })
+                }
+                None => Ok(Request::SetColor {
+                    r: json_int(value, "r")?,
+                    g: json_int(value, "g")?,
+                    b: json_int(value, "b")?,
+                }),
+            },
+            "brightness" => Ok(Request::Brightness(check_level(json_int(value, "level")?)?)),
+            "adjust" => Ok(Request::AdjustBrightness(json_int(value, "delta")?)),
+            "set" => Ok(Request::Set {
+                brightness: match value.get("brightness") {
+                    Some(_) => Some(check_level(json_int(value, "brightness")?)?),
+                    None => None,
+                },
+                color: match value.get("color") {
+                    Some(hex) => {
+                        Some(parse_hex_color(hex.as_str().ok_or_else(|| {
+                            Error::Protocol("invalid \"color\"".into())
+                        })?)?)
+                    }
+                    None => None,
+                },
+            }),
+            "status" => Ok(Request::Status),
+            "subscribe" => Ok(Request::Subscribe),
+            "reload" => Ok(Request::Reload),
+            "history" => match value.get("count") {
+                Some(_) => Ok(Request::History(json_int(value, "count")?)),
+                None => Ok(Request::History(HISTORY_DEFAULT)),
+            },
+            "loglevel" => match value.get("level") {
+                Some(level) => Ok(Request::LogLevel(Some(parse_level(
+                    level
+                        .as_str()
+                        .ok_or_else(|| Error::Protocol("invalid \"level\"".into()))?,
+                )?))),
+                None => Ok(Request::LogLevel(None)),
+            },
+            "stats" => Ok(Request::Stats),
+            "schedule" => {
+                let name = || -> Result<String, Error> {
+                    value
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .map(String::from)
+                        .ok_or_else(|| Error::Protocol("missing \"name\"".into()))
+                };
+                let command = match value.get("action").and_then(Value::as_str) {
+                    None | Some("list") => ScheduleCommand::List,
+                    Some("enable") => ScheduleCommand::Enable(name()?),
+                    Some("disable") => ScheduleCommand::Disable(name()?),
+                    Some(other) => {
+                        return Err(Error::Protocol(format!("unknown schedule action: {other}")));
+                    }
+                };
+                Ok(Request::Schedule(command))
+            }
+            _ => Err(Error::Protocol(format!("unknown command: {cmd}"))),
+        }
+    }
+}
+
+fn parse_level(name: &str) -> Result<Level, Error> {
+    Level::parse(name).ok_or_else(|| Error::Protocol(format!("unknown log level: {name}")))
+}
+
+/// Rejects out-of-range levels at parse time, so that they never reach the
+/// write queue where they could override valid requests of a batch.
+fn check_level(level: u8) -> Result<u8, Error> {
+    if level > 9 {
+        return Err(Error::InvalidBrightness(level));
+    }
+    Ok(level)
+}
+
+fn json_int<T: TryFrom<i64>>(value: &Value, key: &str) -> Result<T, Error> {
+    value
+        .get(key)
+        .ok_or_else(|| Error::Protocol(format!("missing \"{key}\"")))?
+        .as_i64()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| Error::Protocol(format!("invalid \"{key}\"")))
+}
+
+/// Successful reply to a request: a human-readable message for the text
+/// framing plus structured fields for the JSON framing.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub message: String,
+    pub result: Vec<(String, Value)>,
+    /// Extra lines following the `OK` line in text framing; JSON clients
+    /// get the same data in `result`.
+    pub lines: Vec<String>,
+}
+
+impl Response {
+    pub fn info(message: impl Into<String>) -> Self {
+        Response {
+            message: message.into(),
+            result: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.result.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn render(&self, framing: Framing, id: Option<&Value>) -> String {
+        match framing {
+            Framing::Text => {
+                let mut reply = ok_info_response(&self.message);
+                for line in &self.lines {
+                    reply.push_str(line);
+                    reply.push('\n');
+                }
+                reply
+            }
+            Framing::Json => {
+                let mut reply = reply_header(id, true);
+                reply.push(("message".into(), self.message.as_str().into()));
+                reply.push(("result".into(), Value::Object(self.result.clone())));
+                format!("{}\n", Value::Object(reply))
+            }
+        }
+    }
+}
+
+/// Whether the backlight is lit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    On,
+    Off,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::On => f.write_str("on"),
+            Mode::Off => f.write_str("off"),
+        }
+    }
+}
+
+/// Daemon health as seen by subscribers: degraded while the EC misbehaves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    Ok,
+    Degraded(String),
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Ok => f.write_str("ok"),
+            Health::Degraded(reason) => write!(f, "degraded {reason}"),
+        }
+    }
+}
+
+/// State change pushed to subscribed connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Brightness(u8),
+    Color { r: u8, g: u8, b: u8 },
+    Mode(Mode),
+    Health(Health),
+}
+
+impl Event {
+    pub fn to_line(&self) -> String {
+        match self {
+            Event::Brightness(level) => format!("EVENT brightness {level}"),
+            Event::Color { r, g, b } => format!("EVENT color #{r:02X}{g:02X}{b:02X}"),
+            Event::Mode(mode) => format!("EVENT mode {mode}"),
+            Event::Health(health) => format!("EVENT health {health}"),
+        }
+    }
+
+    pub fn render(&self, framing: Framing) -> String {
+        match framing {
+            Framing::Text => format!("{}\n", self.to_line()),
+            Framing::Json => {
+                let fields: Vec<(String, Value)> = match self {
+                    Event::Brightness(level) => vec![
+                        ("event".into(), "brightness".into()),
+                        ("brightness".into(), (*level).into()),
+                    ],
+                    Event::Color { r, g, b } => vec![
+                        ("event".into(), "color".into()),
+                        ("color".into(), format!("#{r:02X}{g:02X}{b:02X}").into()),
+                    ],
+                    Event::Mode(mode) => vec![
+                        ("event".into(), "mode".into()),
+                        ("mode".into(), mode.to_string().into()),
+                    ],
+                    Event::Health(health) => {
+                        let mut fields = vec![("event".into(), "health".into())];
+                        match health {
+                            Health::Ok => fields.push(("health".into(), "ok".into())),
+                            Health::Degraded(reason) => {
+                                fields.push(("health".into(), "degraded".into()));
+                                fields.push(("reason".into(), reason.as_str().into()));
+                            }
+                        }
+                        fields
+                    }
+                };
+                format!("{}\n", Value::Object(fields))
+            }
+        }
+    }
+}
+
+pub fn render_error(error: &Error, framing: Framing, id: Option<&Value>) -> String {
+    match framing {
+        Framing::Text => err_response(&format!("{} {error}", error.code().as_str())),
+        Framing::Json => {
+            let mut reply = reply_header(id, false);
+            reply.push((
+                "error".into(),
+                Value::Object(vec![
+                    ("code".into(), error.code().number().into()),
+                    ("name".into(), error.code().as_str().into()),
+                    ("message".into(), error.to_string().into()),
+                ]),
+            ));
+            format!("{}\n", Value::Object(reply))
+        }
+    }
+}
+
+fn reply_header(id: Option<&Value>, ok: bool) -> Vec<(String, Value)> {
+    let mut reply = Vec::new();
+    if let Some(id) = id {
+        reply.push(("id".into(), id.clone()));
+    }
+    reply.push(("ok".into(), ok.into()));
+    reply
+}
+
+/// Daemon description returned in reply to `HELLO`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub protocol: u32,
+    pub version: String,
+    pub backend: String,
+    pub model: String,
+    pub zones: u8,
+    pub verbs: Vec<String>,
+}
+
+impl Capabilities {
+    pub fn to_line(&self) -> String {
+        format!(
+            "protocol={} version={} backend={} model={} zones={} verbs={}",
+            self.protocol,
+            self.version,
+            self.backend,
+            self.model,
+            self.zones,
+            self.verbs.join(",")
+        )
+    }
+
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let mut caps = Capabilities {
+            protocol: 0,
+            version: String::new(),
+            backend: String::new(),
+            model: String::new(),
+            zones: 0,
+            verbs: Vec::new(),
+        };
+        for field in line.split_whitespace() {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| Error::Protocol(format!("malformed field: {field}")))?;
+            match key {
+                "protocol" => {
+                    caps.protocol = value
+                        .parse()
+                        .map_err(|_| Error::Protocol("invalid protocol version".into()))?
+                }
+                "version" => caps.version = value.into(),
+                "backend" => caps.backend = value.into(),
+                "model" => caps.model = value.into(),
+                "zones" => {
+                    caps.zones = value
+                        .parse()
+                        .map_err(|_| Error::Protocol("invalid zone count".into()))?
+                }
+                "verbs" => caps.verbs = value.split(',').map(String::from).collect(),
+                // Unknown fields come from newer daemons; ignore them.
+                _ => {}
+            }
+        }
+        if caps.protocol == 0 {
+            return Err(Error::Protocol("missing protocol version".into()));
         }
+        Ok(caps)
+    }
+
+    pub fn to_fields(&self) -> Vec<(String, Value)> {
+        vec![
+            ("protocol".into(), self.protocol.into()),
+            ("version".into(), self.version.as_str().into()),
+            ("backend".into(), self.backend.as_str().into()),
+            ("model".into(), self.model.as_str().into()),
+            ("zones".into(), self.zones.into()),
+            (
+                "verbs".into(),
+                Value::Array(self.verbs.iter().map(|v| v.as_str().into()).collect()),
+            ),
+        ]
     }
 }
 
 pub fn parse_hex_color(s: &str) -> Result<(u8, u8, u8), Error> {
     let s = s.strip_prefix('#').unwrap_or(s);
-    if s.len() != 6 {
+    if s.len() != 6 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
         return Err(Error::InvalidColor(s.into()));
     }
     let r = u8::from_str_radix(&s[0..2], 16).map_err(|_| Error::InvalidColor(s.into()))?;
@@ -105,3 +663,52 @@ pub fn ok_info_response(msg: &str) -> String {
 pub fn err_response(msg: &str) -> String {
     format!("ERR {msg}\n")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(parse_hex_color("#ff8000").unwrap(), (0xff, 0x80, 0x00));
+        assert_eq!(parse_hex_color("00FFaa").unwrap(), (0x00, 0xff, 0xaa));
+        for bad in [
+            "", "#fff", "ff80000", "#gg0000", "+f+f+f", "é1234", "#ééé", "ff00é",
+        ] {
+            assert!(parse_hex_color(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_levels() {
+        assert_eq!(
+            Request::parse("BRIGHTNESS 9").unwrap(),
+            Request::Brightness(9)
+        );
+        assert!(matches!(
+            Request::parse("BRIGHTNESS 12"),
+            Err(Error::InvalidBrightness(12))
+        ));
+        assert!(matches!(
+            Request::parse("SET brightness=12 color=#ffffff"),
+            Err(Error::InvalidBrightness(12))
+        ));
+        let json = Value::parse(r#"{"cmd":"set","brightness":10}"#).unwrap();
+        assert!(matches!(
+            Request::from_json(&json),
+            Err(Error::InvalidBrightness(10))
+        ));
+        let json = Value::parse(r#"{"cmd":"brightness","level":10}"#).unwrap();
+        assert!(Request::from_json(&json).is_err());
+    }
+
+    #[test]
+    fn rejects_non_ascii_colors_in_requests() {
+        assert!(Request::parse("COLOR é1234").is_err());
+        assert!(Request::parse("SET color=é1234").is_err());
+        assert!(
+            Request::from_json(&Value::parse(r##"{"cmd":"color","color":"#ééé"}"##).unwrap())
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reactions to system events, configured under `[rules]` as
+//! `trigger = action`, for example `ac = brightness 9`.
+
+use std::fmt;
+
+use crate::protocol::{self, Request};
+
+/// Something that happened to the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trigger {
+    /// Switched to AC power.
+    Ac,
+    /// Switched to battery power.
+    Battery,
+    InputAdded,
+    InputRemoved,
+    /// A display was connected or disconnected.
+    DisplayChanged,
+    Resume,
+}
+
+impl Trigger {
+    pub const NAMES: &[&str] = &[
+        "ac",
+        "battery",
+        "input_added",
+        "input_removed",
+        "display_changed",
+        "resume",
+    ];
+    const ALL: [Trigger; 6] = [
+        Trigger::Ac,
+        Trigger::Battery,
+        Trigger::InputAdded,
+        Trigger::InputRemoved,
+        Trigger::DisplayChanged,
+        Trigger::Resume,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        let index = Self::NAMES.iter().position(|n| *n == name)?;
+        Some(Self::ALL[index])
+    }
+
+    /// Whether the trigger comes from kernel uevents.
+    pub fn needs_uevents(self) -> bool {
+        self != Trigger::Resume
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(Self::NAMES[*self as usize])
+    }
+}
+
+/// What to do: `on`, `off`, `brightness N` and/or `color RRGGBB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Action {
+    pub brightness: Option<u8>,
+    pub color: Option<(u8, u8, u8)>,
+}
+
+impl Action {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut action = Action {
+            brightness: None,
+            color: None,
+        };
+        let mut words = text.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "on" => action.brightness = Some(9),
+                "off" => action.brightness = Some(0),
+                "brightness" => {
+                    let level = words.next().ok_or("brightness needs a level")?;
+                    action.brightness = Some(
+                        level
+                            .parse()
+                            .ok()
+                            .filter(|&l| l <= 9)
+                            .ok_or_else(|| format!("expected 0-9, got {level}"))?,
+                    );
+                }
+                "color" => {
+                    let color = words.next().ok_or("color needs RRGGBB")?;
+                    action.color =
+                        Some(protocol::parse_hex_color(color).map_err(|e| e.to_string())?);
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown action {word} (expected on, off, brightness or color)"
+                    ));
+                }
+            }
+        }
+        if action.brightness.is_none() && action.color.is_none() {
+            return Err("empty action".into());
+        }
+        Ok(action)
+    }
+
+    /// The equivalent client request, applied as one transaction.
+    pub fn request(&self) -> Request {
+        Request::Set {
+            brightness: self.brightness,
+            color: self.color,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut words = Vec::new();
+        if let Some(level) = self.brightness {
+            words.push(format!("brightness {level}"));
+        }
+        if let Some((r, g, b)) = self.color {
+            words.push(format!("color #{r:02X}{g:02X}{b:02X}"));
+        }
+        f.write_str(&words.join(" "))
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runtime directory, single-instance lock and control socket.
+
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use nix::fcntl::{Flock, FlockArg};
+use nix::unistd::{Gid, Uid, chown, getuid};
+
+use crate::detach;
+use crate::error::{Error, Result};
+
+/// Ownership and modes for the runtime directory and the socket in it.
+pub struct Layout {
+    pub owner: Option<Uid>,
+    pub group: Option<Gid>,
+    pub dir_mode: u32,
+    pub socket_mode: u32,
+}
+
+/// Holds the instance lock for as long as the daemon runs and removes the
+/// socket and pidfile when dropped.
+pub struct Runtime {
+    socket_path: PathBuf,
+    pidfile: PathBuf,
+    _lock: Flock<File>,
+}
+
+impl Runtime {
+    /// Prepares the socket's directory, takes the instance lock, clears a
+    /// stale socket left by a dead daemon and binds a fresh one. Refuses to
+    /// start if another instance is alive.
+    pub fn setup(socket_path: &Path, layout: &Layout) -> Result<(Self, UnixListener)> {
+        let dir = socket_path.parent().ok_or_else(|| {
+            Error::Config(format!("invalid socket path: {}", socket_path.display()))
+        })?;
+        Self::prepare_dir(dir, layout)?;
+
+        let lock_path = socket_path.with_extension("lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&lock_path)?;
+        let lock = Flock::lock(file, FlockArg::LockExclusiveNonblock).map_err(|_| {
+            Error::Busy(format!(
+                "another daemon instance holds {}",
+                lock_path.display()
+            ))
+        })?;
+
+        match fs::symlink_metadata(socket_path) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                if UnixStream::connect(socket_path).is_ok() {
+                    return Err(Error::Busy(format!(
+                        "a daemon is already listening on {}",
+                        socket_path.display()
+                    )));
+                }
+                fs::remove_file(socket_path)?;
+            }
+            Ok(_) => {
+                return Err(Error::Config(format!(
+                    "{} exists and is not a socket; refusing to remove it",
+                    socket_path.display()
+                )));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::Io(e)),
+        }
+
+        let listener = UnixListener::bind(socket_path)?;
+        listener.set_nonblocking(true)?;
+        if getuid().is_root() {
+            chown(socket_path, layout.owner, layout.group).map_err(|e| Error::Io(e.into()))?;
+        }
+        fs::set_permissions(socket_path, fs::Permissions::from_mode(layout.socket_mode))?;
+
+        // Written only while holding the lock, so it always names the live daemon.
+        let pidfile = detach::pidfile(socket_path);
+        // Never through a symlink planted in the directory, since we may
+        // still be root here.
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(0o644)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&pidfile)?;
+        file.write_all(format!("{}\n", std::process::id()).as_bytes())?;
+        // An existing file keeps its mode on open, and the umask may have
+        // narrowed a new one.
+        file.set_permissions(fs::Permissions::from_mode(0o644))?;
+
+        let runtime = Runtime {
+            socket_path: socket_path.to_path_buf(),
+            pidfile,
+            _lock: lock,
+        };
+        Ok((runtime, listener))
+    }
+
+    /// Creates the socket's directory, or adopts it if an earlier run of
+    /// the daemon created it. Directories owned by anyone else (say, `/tmp`
+    /// for a test socket) are used as they are.
+    fn prepare_dir(dir: &Path, layout: &Layout) -> Result<()> {
+        let adopt = match fs::symlink_metadata(dir) {
+            Ok(meta) if meta.is_dir() => layout.owner.is_some_and(|uid| meta.uid() == uid.as_raw()),
+            Ok(_) => {
+                return Err(Error::Config(format!(
+                    "{} exists and is not a directory",
+                    dir.display()
+                )));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                DirBuilder::new().mode(layout.dir_mode).create(dir)?;
+                true
+            }
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        if adopt && getuid().is_root() {
+            chown(dir, layout.owner, layout.group).map_err(|e| Error::Io(e.into()))?;
+            fs::set_permissions(dir, fs::Permissions::from_mode(layout.dir_mode))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket_path);
+        let _ = fs::remove_file(&self.pidfile);
+    }
+}
@@ -0,0 +1,409 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Privilege drop and confinement of the daemon.
+//!
+//! Once `/dev/port` and the socket are open the daemon no longer needs
+//! root: it switches to an unprivileged user, then restricts itself with
+//! Landlock (filesystem) and a seccomp filter (syscalls). The Landlock
+//! ruleset is built while still root, so that root-only paths such as
+//! `/dev/port` can be granted.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{User, chown, getuid, setgroups, setresgid, setresuid};
+
+use crate::config::DaemonConfig;
+use crate::error::{Error, Result};
+use crate::log::{debug, info, warning};
+
+const CAP_SYS_RAWIO: u32 = 17;
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+// Landlock ABI, see <linux/landlock.h>.
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+/// Every access right of ABI v1 (execute .. make_sym).
+const ACCESS_ABI_V1: u64 = (1 << 13) - 1;
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+
+const ACCESS_READ: u64 = ACCESS_READ_FILE | ACCESS_READ_DIR;
+
+#[repr(C)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: i32,
+}
+
+#[repr(C)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Drops root and confines the process. `readable` lists extra paths the
+/// daemon reads later (e.g. the config file for RELOAD) and `writable` the
+/// directories it must still modify (e.g. the socket directory); the state
+/// directory is always writable.
+pub fn enter(config: &DaemonConfig, readable: &[&Path], writable: &[&Path]) -> Result<()> {
+    let root = getuid().is_root();
+    if root {
+        fs::create_dir_all(&config.state_dir)?;
+    }
+
+    let ruleset = if config.sandbox {
+        // The system directories and the config file may legitimately be
+        // missing; everything else the daemon writes to must exist.
+        let mut rules: Vec<(PathBuf, u64, bool)> =
+            ["/etc", "/usr", "/lib", "/lib64", "/proc", "/sys"]
+                .iter()
+                .map(|p| (PathBuf::from(p), ACCESS_READ | ACCESS_EXECUTE, false))
+                .collect();
+        rules.extend(
+            readable
+                .iter()
+                .map(|p| (p.to_path_buf(), ACCESS_READ, false)),
+        );
+        rules.push((config.state_dir.clone(), u64::MAX, root));
+        rules.extend(writable.iter().map(|p| (p.to_path_buf(), u64::MAX, true)));
+        if config.keep_rawio {
+            rules.push((
+                PathBuf::from(crate::ec::BACKEND),
+                ACCESS_READ_FILE | ACCESS_WRITE_FILE,
+                true,
+            ));
+        }
+        build_ruleset(&rules)?
+    } else {
+        None
+    };
+
+    if root {
+        drop_privileges(config)?;
+    } else {
+        info!("not running as root, keeping current user");
+    }
+
+    if !config.sandbox {
+        return Ok(());
+    }
+
+    let rc = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+    if rc != 0 {
+        return Err(Error::Io(std::io::Error::last_os_error()));
+    }
+
+    match ruleset {
+        Some(ruleset) => {
+            let rc = unsafe {
+                libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0u32)
+            };
+            if rc != 0 {
+                return Err(Error::Io(std::io::Error::last_os_error()));
+            }
+        }
+        None => warning!("Landlock not supported by this kernel, skipping"),
+    }
+    install_seccomp_filter()
+}
+
+fn drop_privileges(config: &DaemonConfig) -> Result<()> {
+    let user = User::from_name(&config.user)
+        .map_err(|e| Error::Io(e.into()))?
+        .ok_or_else(|| Error::Config(format!("unknown daemon user: {}", config.user)))?;
+
+    chown(&config.state_dir, Some(user.uid), Some(user.gid)).map_err(|e| Error::Io(e.into()))?;
+    fs::set_permissions(&config.state_dir, fs::Permissions::from_mode(0o750))?;
+
+    if config.keep_rawio {
+        // Keep the permitted set across setuid so CAP_SYS_RAWIO can be
+        // re-raised below; everything else is dropped by capset.
+        if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) } != 0 {
+            return Err(Error::Io(std::io::Error::last_os_error()));
+        }
+    }
+
+    setgroups(&[]).map_err(|e| Error::Io(e.into()))?;
+    setresgid(user.gid, user.gid, user.gid).map_err(|e| Error::Io(e.into()))?;
+    setresuid(user.uid, user.uid, user.uid).map_err(|e| Error::Io(e.into()))?;
+
+    if config.keep_rawio {
+        let header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let rawio = 1u32 << CAP_SYS_RAWIO;
+        let data = [
+            CapData {
+                effective: rawio,
+                permitted: rawio,
+                inheritable: 0,
+            },
+            CapData {
+                effective: 0,
+                permitted: 0,
+                inheritable: 0,
+            },
+        ];
+        let rc = unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) };
+        if rc != 0 {
+            return Err(Error::Io(std::io::Error::last_os_error()));
+        }
+    }
+
+    info!(
+        "running as {} (uid {}, gid {})",
+        config.user, user.uid, user.gid
+    );
+    Ok(())
+}
+
+/// Builds a Landlock ruleset granting each `(path, access, required)`
+/// rule. Returns `Ok(None)` if Landlock is unavailable. A missing path is
+/// skipped unless it is required; any other failure to open it is an error.
+fn build_ruleset(rules: &[(PathBuf, u64, bool)]) -> Result<Option<OwnedFd>> {
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<LandlockRulesetAttr>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 1 {
+        return Ok(None);
+    }
+
+    let mut handled = ACCESS_ABI_V1;
+    if abi >= 3 {
+        handled |= ACCESS_TRUNCATE;
+    }
+    let attr = LandlockRulesetAttr {
+        handled_access_fs: handled,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr,
+            std::mem::size_of::<LandlockRulesetAttr>(),
+            0u32,
+        )
+    };
+    if fd < 0 {
+        return Err(Error::Io(std::io::Error::last_os_error()));
+    }
+    let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+    for (path, access, required) in rules {
+        let file = match fs::File::options()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+            .open(path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => {
+                debug!(
+                    "sandbox: {} does not exist, not granting it",
+                    path.display()
+                );
+                continue;
+            }
+            Err(e) => {
+                return Err(Error::Io(std::io::Error::new(
+                    e.kind(),
+                    format!("sandbox: cannot open {}: {e}", path.display()),
+                )));
+            }
+        };
+        let mut allowed = access & handled;
+        if !file.metadata()?.is_dir() {
+            // Directory-only rights are rejected on files.
+            allowed &= ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE;
+        }
+        let beneath = LandlockPathBeneathAttr {
+            allowed_access: allowed,
+            parent_fd: file.as_raw_fd(),
+        };
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &beneath,
+                0u32,
+            )
+        };
+        if rc != 0 {
+            return Err(Error::Io(std::io::Error::last_os_error()));
+        }
+    }
+    Ok(Some(ruleset))
+}
+
+/// Syscalls the daemon never needs and an attacker would love. They fail
+/// with EPERM rather than killing the process so that a stray call from a
+/// library shows up as an error instead of a crash.
+#[cfg(target_arch = "x86_64")]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_move_mount,
+    libc::SYS_open_tree,
+    libc::SYS_fsopen,
+    libc::SYS_fsmount,
+    libc::SYS_fsconfig,
+    libc::SYS_fspick,
+    libc::SYS_mount_setattr,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_setuid,
+    libc::SYS_setgid,
+    libc::SYS_setreuid,
+    libc::SYS_setregid,
+    libc::SYS_setresuid,
+    libc::SYS_setresgid,
+    libc::SYS_setgroups,
+    libc::SYS_setfsuid,
+    libc::SYS_setfsgid,
+    libc::SYS_capset,
+    libc::SYS_iopl,
+    libc::SYS_ioperm,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_userfaultfd,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_acct,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_clock_adjtime,
+    libc::SYS_adjtimex,
+    libc::SYS_personality,
+    libc::SYS_quotactl,
+    libc::SYS_fanotify_init,
+];
+
+#[cfg(target_arch = "x86_64")]
+fn install_seccomp_filter() -> Result<()> {
+    const AUDIT_ARCH_X86_64: u32 = 0xC000_003E;
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+    // Offsets into struct seccomp_data.
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+
+    let stmt = |code: u32, k: u32| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |code: u32, k: u32, jt: u8, jf: u8| libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    };
+    let deny = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+    let mut filter = vec![
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARCH),
+        jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            AUDIT_ARCH_X86_64,
+            1,
+            0,
+        ),
+        stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, NR),
+        jump(
+            libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+            X32_SYSCALL_BIT,
+            0,
+            1,
+        ),
+        stmt(libc::BPF_RET | libc::BPF_K, deny),
+    ];
+    for &nr in DENIED_SYSCALLS {
+        filter.push(jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            nr as u32,
+            0,
+            1,
+        ));
+        filter.push(stmt(libc::BPF_RET | libc::BPF_K, deny));
+    }
+    filter.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            0u32,
+            &prog,
+        )
+    };
+    if rc != 0 {
+        return Err(Error::Io(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn install_seccomp_filter() -> Result<()> {
+    warning!("seccomp filter is only built for x86_64, skipping");
+    Ok(())
+}
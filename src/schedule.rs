@@ -0,0 +1,552 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Time-of-day schedules, configured under `[schedule]` as
+//! `name = [days] HH:MM[-HH:MM] action`.
+//!
+//! A single time applies the action at that time. A range applies it at the
+//! start and puts the previous state back at the end. The next change is
+//! waited for with a `CLOCK_REALTIME` timerfd, which the kernel cancels
+//! when the clock is set, and /etc is watched for a new /etc/localtime, so
+//! clock and time zone changes take effect at once.
+
+use std::fmt;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+
+use crate::error::{Error, Result};
+use crate::json::Value;
+use crate::log::{debug, info, warning};
+use crate::protocol::Request;
+use crate::rules::Action;
+use crate::tz::{self, TimeZone};
+
+const DAY: i64 = 86_400;
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+/// Day masks, bit 0 being Sunday.
+const DAILY: u8 = 0b111_1111;
+const WEEKDAYS: u8 = 0b011_1110;
+const WEEKENDS: u8 = 0b100_0001;
+/// How far back missed single-time schedules are still applied, as after a
+/// long suspend.
+const CATCH_UP: i64 = 7 * DAY;
+
+/// Brightness and color, as saved when a range starts.
+pub type Backlight = (u8, Option<(u8, u8, u8)>);
+
+/// One configured schedule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    /// Days the schedule starts on.
+    pub days: u8,
+    /// Minutes after midnight.
+    pub start: u32,
+    /// End of a range, which may be on the next day.
+    pub end: Option<u32>,
+    pub action: Action,
+}
+
+impl Schedule {
+    pub fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut words = text.split_whitespace().peekable();
+        let first = words.next().ok_or("empty schedule")?;
+        let (days, times) = match days(first) {
+            Some(days) => (days, words.next().ok_or("missing time")?),
+            None => (DAILY, first),
+        };
+        let (start, end) = match times.split_once(['-', '\u{2013}']) {
+            Some((start, end)) => (minutes(start)?, Some(minutes(end)?)),
+            None => (minutes(times)?, None),
+        };
+        let action = Action::parse(&words.collect::<Vec<_>>().join(" "))?;
+        Ok(Schedule {
+            days,
+            start,
+            end,
+            action,
+        })
+    }
+
+    fn runs_on(&self, day: i64) -> bool {
+        self.days & (1 << tz::weekday(day)) != 0
+    }
+
+    /// UTC start and end of the occurrence starting on local `day`.
+    fn occurrence(&self, zone: &TimeZone, day: i64) -> (i64, Option<i64>) {
+        let start = zone.to_utc(day * DAY + self.start as i64 * 60);
+        let end = self.end.map(|end| {
+            let end_day = if end <= self.start { day + 1 } else { day };
+            zone.to_utc(end_day * DAY + end as i64 * 60)
+        });
+        (start, end)
+    }
+
+    /// Whether a range covers `now`.
+    fn covers(&self, zone: &TimeZone, now: i64) -> bool {
+        let today = zone.to_local(now).div_euclid(DAY);
+        (today - 1..=today).filter(|&d| self.runs_on(d)).any(|d| {
+            let (start, end) = self.occurrence(zone, d);
+            end.is_some_and(|end| start <= now && now < end)
+        })
+    }
+
+    /// Whether a single time came up in `(from, to]`.
+    fn due(&self, zone: &TimeZone, from: i64, to: i64) -> bool {
+        let from = from.max(to - CATCH_UP);
+        let first = zone.to_local(from).div_euclid(DAY) - 1;
+        let last = zone.to_local(to).div_euclid(DAY) + 1;
+        (first..=last)
+            .filter(|&d| self.runs_on(d))
+            .map(|d| self.occurrence(zone, d).0)
+            .any(|t| from < t && t <= to)
+    }
+
+    /// The next start or end after `now`.
+    fn next_change(&self, zone: &TimeZone, now: i64) -> Option<i64> {
+        let today = zone.to_local(now).div_euclid(DAY);
+        (today - 1..=today + 8)
+            .filter(|&d| self.runs_on(d))
+            .flat_map(|d| {
+                let (start, end) = self.occurrence(zone, d);
+                [Some(start), end]
+            })
+            .flatten()
+            .filter(|&t| t > now)
+            .min()
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.days {
+            DAILY => {}
+            WEEKDAYS => f.write_str("weekdays ")?,
+            WEEKENDS => f.write_str("weekends ")?,
+            days => {
+                let names: Vec<&str> = (0..7)
+                    .filter(|d| days & (1 << d) != 0)
+                    .map(|d| DAY_NAMES[d])
+                    .collect();
+                write!(f, "{} ", names.join(","))?;
+            }
+        }
+        write!(f, "{:02}:{:02}", self.start / 60, self.start % 60)?;
+        if let Some(end) = self.end {
+            write!(f, "-{:02}:{:02}", end / 60, end % 60)?;
+        }
+        write!(f, " {}", self.action)
+    }
+}
+
+/// `daily`, `weekdays`, `weekends` or a list such as `mon,wed-fri`.
+fn days(word: &str) -> Option<u8> {
+    match word {
+        "daily" => return Some(DAILY),
+        "weekdays" => return Some(WEEKDAYS),
+        "weekends" => return Some(WEEKENDS),
+        _ => {}
+    }
+    let day = |name: &str| DAY_NAMES.iter().position(|d| *d == name);
+    let mut mask = 0;
+    for part in word.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+            None => (day(part)?, day(part)?),
+        };
+        let mut d = first;
+        loop {
+            mask |= 1 << d;
+            if d == last {
+                break;
+            }
+            d = (d + 1) % 7;
+        }
+    }
+    Some(mask)
+}
+
+/// `HH:MM` in minutes after midnight.
+fn minutes(text: &str) -> std::result::Result<u32, String> {
+    text.split_once(':')
+        .and_then(|(h, m)| {
+            let h: u32 = h.parse().ok().filter(|&h| h < 24)?;
+            let m: u32 = m.parse().ok().filter(|&m| m < 60)?;
+            Some(h * 60 + m)
+        })
+        .ok_or_else(|| format!("expected HH:MM, got {text}"))
+}
+
+struct Entry {
+    name: String,
+    schedule: Schedule,
+    enabled: bool,
+    /// A range is in effect.
+    active: bool,
+    /// Brightness and color to put back when the range ends.
+    saved: Option<Backlight>,
+}
+
+pub struct Scheduler {
+    entries: Vec<Entry>,
+    zone: TimeZone,
+    timer: TimerFd,
+    /// Watches /etc for a new localtime.
+    watch: Option<Inotify>,
+    /// When schedules were last checked.
+    last: i64,
+}
+
+impl Scheduler {
+    pub fn new(schedules: &[(String, Schedule)]) -> Result<Self> {
+        let timer = TimerFd::new(
+            ClockId::CLOCK_REALTIME,
+            TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC,
+        )
+        .map_err(|e| Error::Io(e.into()))?;
+        let watch = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .and_then(|watch| {
+                watch.add_watch(
+                    "/etc",
+                    AddWatchFlags::IN_CREATE
+                        | AddWatchFlags::IN_MOVED_TO
+                        | AddWatchFlags::IN_CLOSE_WRITE
+                        | AddWatchFlags::IN_DELETE,
+                )?;
+                Ok(watch)
+            })
+            .inspect_err(|e| warning!("cannot watch {} for changes: {e}", tz::LOCALTIME))
+            .ok();
+        let mut scheduler = Scheduler {
+            entries: Vec::new(),
+            zone: load_zone(),
+            timer,
+            watch,
+            last: now(),
+        };
+        scheduler.reconfigure(schedules);
+        Ok(scheduler)
+    }
+
+    /// Takes a new set of schedules. Those that keep their name keep
+    /// being enabled or disabled, and a range in effect still puts back
+    /// what it saved.
+    pub fn reconfigure(&mut self, schedules: &[(String, Schedule)]) {
+        let mut old = std::mem::take(&mut self.entries);
+        self.entries = schedules
+            .iter()
+            .map(|(name, schedule)| {
+                let kept = old
+                    .iter()
+                    .position(|e| e.name == *name)
+                    .map(|i| old.swap_remove(i));
+                Entry {
+                    name: name.clone(),
+                    schedule: schedule.clone(),
+                    enabled: kept.as_ref().is_none_or(|e| e.enabled),
+                    active: kept.as_ref().is_some_and(|e| e.active),
+                    saved: kept.and_then(|e| e.saved),
+                }
+            })
+            .collect();
+    }
+
+    pub fn fds(&self) -> impl Iterator<Item = BorrowedFd<'_>> {
+        std::iter::once(self.timer.as_fd()).chain(self.watch.as_ref().map(Inotify::as_fd))
+    }
+
+    /// Pauses or resumes a schedule; false if there is none by that name.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.entries.iter_mut().find(|e| e.name == name) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies whatever came due since the last call, given the current
+    /// brightness and color, and arms the timer for the next change.
+    /// Returns the requests to make, each with the schedule it is for.
+    pub fn update(&mut self, state: Backlight) -> Vec<(String, Request)> {
+        if self.timer.wait().is_ok() {
+            debug!("schedule timer fired or the clock was set");
+        }
+        if let Some(watch) = &self.watch
+            && let Ok(events) = watch.read_events()
+            && events
+                .iter()
+                .any(|e| e.name.as_deref().is_some_and(|n| n == "localtime"))
+        {
+            info!("time zone changed");
+            self.zone = load_zone();
+        }
+
+        let now = now();
+        let zone = &self.zone;
+        let mut requests = Vec::new();
+        for entry in &mut self.entries {
+            let source = format!("schedule {}", entry.name);
+            let action = entry.schedule.action;
+            if entry.schedule.end.is_none() {
+                if entry.enabled && entry.schedule.due(zone, self.last, now) {
+                    info!("schedule {}: {action}", entry.name);
+                    requests.push((source, action.request()));
+                }
+                continue;
+            }
+            let wanted = entry.enabled && entry.schedule.covers(zone, now);
+            if wanted && !entry.active {
+                info!("schedule {} started: {action}", entry.name);
+                entry.saved = Some(state);
+                requests.push((source, action.request()));
+            } else if !wanted && entry.active {
+                info!("schedule {} ended", entry.name);
+                if let Some((brightness, color)) = entry.saved.take() {
+                    requests.push((
+                        source,
+                        Request::Set {
+                            brightness: Some(brightness),
+                            color,
+                        },
+                    ));
+                }
+            }
+            entry.active = wanted;
+        }
+        self.last = now;
+        self.arm(now);
+        requests
+    }
+
+    fn arm(&self, now: i64) {
+        let next = self
+            .entries
+            .iter()
+            .filter(|e| e.enabled)
+            .filter_map(|e| e.schedule.next_change(&self.zone, now))
+            .min();
+        let result = match next {
+            Some(at) => self.timer.set(
+                Expiration::OneShot(TimeSpec::new(at, 0)),
+                TimerSetTimeFlags::TFD_TIMER_ABSTIME | TimerSetTimeFlags::TFD_TIMER_CANCEL_ON_SET,
+            ),
+            None => self.timer.unset(),
+        };
+        if let Err(e) = result {
+            warning!("cannot set the schedule timer: {e}");
+        }
+    }
+
+    /// One line per schedule: name, state, next change and definition.
+    pub fn lines(&self) -> Vec<String> {
+        let now = now();
+        self.entries
+            .iter()
+            .map(|e| {
+                format!(
+                    "{} {} {} next={} {}",
+                    e.name,
+                    if e.enabled { "enabled" } else { "disabled" },
+                    if e.active { "active" } else { "idle" },
+                    self.next(e, now).unwrap_or_else(|| "-".into()),
+                    e.schedule
+                )
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let now = now();
+        Value::Array(
+            self.entries
+                .iter()
+                .map(|e| {
+                    Value::Object(vec![
+                        ("name".into(), e.name.as_str().into()),
+                        ("enabled".into(), e.enabled.into()),
+                        ("active".into(), e.active.into()),
+                        (
+                            "next".into(),
+                            self.next(e, now).map_or(Value::Null, Value::from),
+                        ),
+                        ("schedule".into(), e.schedule.to_string().into()),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    /// Local time of the entry's next change, as `YYYY-MM-DDTHH:MM`.
+    fn next(&self, entry: &Entry, now: i64) -> Option<String> {
+        if !entry.enabled {
+            return None;
+        }
+        let local = self
+            .zone
+            .to_local(entry.schedule.next_change(&self.zone, now)?);
+        let (year, month, day) = tz::civil_from_days(local.div_euclid(DAY));
+        let minutes = local.rem_euclid(DAY) / 60;
+        Some(format!(
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}",
+            minutes / 60,
+            minutes % 60
+        ))
+    }
+}
+
+fn load_zone() -> TimeZone {
+    TimeZone::load().unwrap_or_else(|e| {
+        warning!("{e}, using UTC for schedules");
+        TimeZone::utc()
+    })
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-19, a Monday.
+    fn monday() -> i64 {
+        tz::days_from_civil(2026, 10, 19)
+    }
+
+    fn at(day: i64, hour: i64, min: i64) -> i64 {
+        day * DAY + hour * 3600 + min * 60
+    }
+
+    fn schedule(text: &str) -> Schedule {
+        Schedule::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parses_schedules() {
+        let s = schedule("weekdays 07:30 brightness 5");
+        assert_eq!((s.days, s.start, s.end), (WEEKDAYS, 450, None));
+        let s = schedule("mon,wed-fri 22:00-06:00 off");
+        assert_eq!((s.days, s.start, s.end), (0b011_1010, 1320, Some(360)));
+        assert_eq!(schedule("sat-mon 10:00 on").days, 0b100_0011);
+        assert_eq!(schedule("12:00 on").days, DAILY);
+        assert_eq!(schedule("21:00\u{2013}23:00 on").end, Some(1380));
+
+        for text in [
+            "weekends 08:00 brightness 2",
+            "mon,wed,fri 22:00-06:00 brightness 1 color #FF6000",
+            "09:15 brightness 9",
+        ] {
+            assert_eq!(schedule(text).to_string(), text);
+        }
+
+        for bad in [
+            "",
+            "daily 07:00",
+            "24:00 on",
+            "07:60 on",
+            "funday 07:00 on",
+            "07:00 dim",
+        ] {
+            assert!(Schedule::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn ranges_cross_midnight() {
+        let zone = TimeZone::utc();
+        let s = schedule("22:00-06:00 off");
+        let day = monday();
+        let cases = [
+            (at(day, 21, 59), false),
+            (at(day, 22, 0), true),
+            (at(day, 23, 30), true),
+            (at(day + 1, 5, 59), true),
+            (at(day + 1, 6, 0), false),
+            (at(day + 1, 12, 0), false),
+        ];
+        for (now, covered) in cases {
+            assert_eq!(s.covers(&zone, now), covered, "at {now}");
+        }
+        assert_eq!(s.next_change(&zone, at(day, 12, 0)), Some(at(day, 22, 0)));
+        assert_eq!(
+            s.next_change(&zone, at(day, 23, 0)),
+            Some(at(day + 1, 6, 0))
+        );
+    }
+
+    #[test]
+    fn follows_weekday_masks() {
+        let zone = TimeZone::utc();
+        let (mon, fri, sat, sun) = (monday(), monday() + 4, monday() + 5, monday() + 6);
+
+        // A range belongs to the day it starts on.
+        let s = schedule("fri 22:00-06:00 off");
+        assert!(!s.covers(&zone, at(fri, 2, 0)));
+        assert!(s.covers(&zone, at(fri, 23, 0)));
+        assert!(s.covers(&zone, at(sat, 2, 0)));
+        assert!(!s.covers(&zone, at(sat, 23, 0)));
+        assert!(!s.covers(&zone, at(sun, 2, 0)));
+        assert_eq!(
+            s.next_change(&zone, at(sat, 12, 0)),
+            Some(at(fri + 7, 22, 0))
+        );
+
+        let s = schedule("weekdays 07:00 on");
+        assert!(s.due(&zone, at(mon, 6, 59), at(mon, 7, 0)));
+        assert!(s.due(&zone, at(fri, 6, 0), at(fri, 8, 0)));
+        assert!(!s.due(&zone, at(mon, 7, 0), at(mon, 7, 1)));
+        assert!(!s.due(&zone, at(sat, 6, 59), at(sat, 7, 1)));
+        assert!(!s.due(&zone, at(sun, 6, 59), at(sun, 7, 1)));
+        assert_eq!(s.next_change(&zone, at(fri, 8, 0)), Some(at(mon + 7, 7, 0)));
+    }
+
+    #[test]
+    fn handles_dst_changes() {
+        let zone = TimeZone::from_rule("CET-1CEST,M3.5.0,M10.5.0/3");
+        let s = schedule("02:30 on");
+
+        // Skipped on 2026-03-29: runs when the clocks jump, at 01:00 UTC.
+        let spring = tz::days_from_civil(2026, 3, 29);
+        assert_eq!(
+            s.next_change(&zone, at(spring, 0, 0)),
+            Some(at(spring, 1, 0))
+        );
+        assert!(s.due(&zone, at(spring, 0, 59), at(spring, 1, 0)));
+        assert!(!s.due(&zone, at(spring, 1, 0), at(spring, 1, 59)));
+
+        // Repeated on 2026-10-25: runs once, at the first 02:30.
+        let autumn = tz::days_from_civil(2026, 10, 25);
+        assert_eq!(
+            s.next_change(&zone, at(autumn, 0, 0)),
+            Some(at(autumn, 0, 30))
+        );
+        assert!(s.due(&zone, at(autumn, 0, 0), at(autumn, 0, 30)));
+        assert!(!s.due(&zone, at(autumn, 0, 30), at(autumn, 3, 0)));
+
+        // A range across the night keeps its wall-clock ends.
+        let s = schedule("22:00-06:00 off");
+        assert!(s.covers(&zone, at(autumn - 1, 20, 0)));
+        assert!(s.covers(&zone, at(autumn, 4, 59)));
+        assert!(!s.covers(&zone, at(autumn, 5, 0)));
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Suspend and resume notifications from logind.
+//!
+//! logind broadcasts `PrepareForSleep(true)` before suspending and
+//! `PrepareForSleep(false)` after waking up. Holding a delay inhibitor makes
+//! it wait (up to `InhibitDelayMaxSec`) until we have dealt with the first
+//! one and closed the inhibitor's descriptor.
+
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+
+use crate::dbus::{self, Arg};
+use crate::error::{Error, Result};
+use crate::log::warning;
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+
+pub struct SleepMonitor {
+    bus: dbus::Connection,
+    inhibitor: Option<OwnedFd>,
+}
+
+impl SleepMonitor {
+    /// Connects to the system bus, subscribes to `PrepareForSleep` and
+    /// takes a delay inhibitor.
+    pub fn connect() -> Result<Self> {
+        let mut bus = dbus::Connection::system()?;
+        bus.add_match(&format!(
+            "type='signal',sender='{LOGIND}',interface='{LOGIND_MANAGER}',member='PrepareForSleep'"
+        ))?;
+        let mut monitor = SleepMonitor {
+            bus,
+            inhibitor: None,
+        };
+        monitor.inhibit();
+        Ok(monitor)
+    }
+
+    /// Takes a delay inhibitor unless one is held already. Without it the
+    /// pre-sleep state may or may not make it to the EC in time, which is
+    /// worth a warning but not worth giving up on resume handling.
+    pub fn inhibit(&mut self) {
+        if self.inhibitor.is_some() {
+            return;
+        }
+        match self.take_inhibitor() {
+            Ok(fd) => self.inhibitor = Some(fd),
+            Err(e) => warning!("cannot delay sleep: {e}"),
+        }
+    }
+
+    fn take_inhibitor(&mut self) -> Result<OwnedFd> {
+        let reply = self.bus.call(
+            LOGIND,
+            LOGIND_PATH,
+            LOGIND_MANAGER,
+            "Inhibit",
+            &[
+                Arg::Str("sleep".into()),
+                Arg::Str(env!("CARGO_PKG_NAME").into()),
+                Arg::Str("Setting the keyboard backlight for sleep".into()),
+                Arg::Str("delay".into()),
+            ],
+        )?;
+        let index = reply.args()?.first().and_then(Arg::as_u32);
+        index
+            .and_then(|i| reply.fds.into_iter().nth(i as usize))
+            .ok_or_else(|| Error::Dbus("Inhibit returned no file descriptor".into()))
+    }
+
+    /// Lets a pending suspend go ahead.
+    pub fn release(&mut self) {
+        self.inhibitor = None;
+    }
+
+    /// Whether a message is buffered already, so polling would miss it.
+    pub fn has_pending(&self) -> bool {
+        self.bus.has_pending()
+    }
+
+    /// Reads one message: `Some(true)` when the system is about to sleep,
+    /// `Some(false)` after resume, `None` for anything else.
+    pub fn next_event(&mut self) -> Result<Option<bool>> {
+        let msg = self.bus.next_message()?;
+        if msg.kind != dbus::SIGNAL
+            || msg.interface.as_deref() != Some(LOGIND_MANAGER)
+            || msg.member.as_deref() != Some("PrepareForSleep")
+        {
+            return Ok(None);
+        }
+        Ok(msg.args()?.first().and_then(Arg::as_bool))
+    }
+}
+
+impl AsFd for SleepMonitor {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.bus.as_fd()
+    }
+}
@@ -0,0 +1,410 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Daemon statistics, exposed through `STATS` and optionally written as a
+//! Prometheus textfile for node_exporter's textfile collector.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::error::{ErrorCode, Result};
+use crate::json::Value;
+
+/// Metric name prefix.
+const PREFIX: &str = "kbdlight_";
+
+/// Upper bounds of the EC latency buckets, in seconds. A command includes
+/// the 50 ms settle delay, so most land between 0.05 and 0.1.
+const LATENCY_BUCKETS: [f64; 10] = [0.01, 0.025, 0.05, 0.06, 0.075, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+/// Cumulative histogram in the Prometheus sense.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            counts: [0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    pub fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&mut self.counts) {
+            if secs <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
+
+/// Counters kept by the EC driver.
+#[derive(Debug, Clone)]
+pub struct EcStats {
+    /// Commands sent to the EC, successful or not.
+    pub transactions: u64,
+    /// Status polls that found the EC busy and had to be repeated.
+    pub retries: u64,
+    /// Commands abandoned because the EC never became ready.
+    pub timeouts: u64,
+    /// Commands not sent because the EC still had them applied.
+    pub skipped: u64,
+    pub latency: Histogram,
+}
+
+impl Default for EcStats {
+    fn default() -> Self {
+        EcStats {
+            transactions: 0,
+            retries: 0,
+            timeouts: 0,
+            skipped: 0,
+            latency: Histogram::new(),
+        }
+    }
+}
+
+/// Request counters kept by the daemon.
+pub struct Stats {
+    started: Instant,
+    requests: BTreeMap<&'static str, u64>,
+    errors: BTreeMap<&'static str, u64>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats {
+            started: Instant::now(),
+            requests: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        }
+    }
+
+    pub fn request(&mut self, verb: &'static str) {
+        *self.requests.entry(verb).or_default() += 1;
+    }
+
+    pub fn error(&mut self, code: ErrorCode) {
+        *self.errors.entry(code.as_str()).or_default() += 1;
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Snapshot of the daemon's state that goes along with the counters.
+pub struct State<'a> {
+    pub brightness: u8,
+    pub color: Option<String>,
+    pub on: bool,
+    pub healthy: bool,
+    pub queued: usize,
+    pub ec: &'a EcStats,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+/// One metric with its samples, e.g. `requests_total` with one sample per verb.
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: Kind,
+    samples: Vec<Sample>,
+}
+
+struct Sample {
+    /// Appended to the family name, for histogram `_bucket`, `_sum`, `_count`.
+    suffix: &'static str,
+    labels: Vec<(&'static str, String)>,
+    value: f64,
+}
+
+impl Sample {
+    fn plain(value: impl Into<f64>) -> Self {
+        Sample {
+            suffix: "",
+            labels: Vec::new(),
+            value: value.into(),
+        }
+    }
+
+    fn labelled(label: &'static str, key: impl Into<String>, value: impl Into<f64>) -> Self {
+        Sample {
+            suffix: "",
+            labels: vec![(label, key.into())],
+            value: value.into(),
+        }
+    }
+
+    fn name(&self, family: &str) -> String {
+        let mut name = format!("{PREFIX}{family}{}", self.suffix);
+        if !self.labels.is_empty() {
+            let labels: Vec<String> = self
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+                .collect();
+            let _ = write!(name, "{{{}}}", labels.join(","));
+        }
+        name
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn counter(name: &'static str, help: &'static str, samples: Vec<Sample>) -> Family {
+    Family {
+        name,
+        help,
+        kind: Kind::Counter,
+        samples,
+    }
+}
+
+fn gauge(name: &'static str, help: &'static str, samples: Vec<Sample>) -> Family {
+    Family {
+        name,
+        help,
+        kind: Kind::Gauge,
+        samples,
+    }
+}
+
+// u64 counters are reported as f64, like Prometheus itself does.
+fn count(n: u64) -> f64 {
+    n as f64
+}
+
+/// A rendered set of metrics.
+pub struct Report(Vec<Family>);
+
+impl Report {
+    pub fn new(stats: &Stats, state: &State<'_>) -> Self {
+        let ec = state.ec;
+        let mut buckets: Vec<Sample> = LATENCY_BUCKETS
+            .iter()
+            .zip(ec.latency.counts)
+            .map(|(bound, n)| Sample {
+                suffix: "_bucket",
+                labels: vec![("le", bound.to_string())],
+                value: count(n),
+            })
+            .collect();
+        buckets.push(Sample {
+            suffix: "_bucket",
+            labels: vec![("le", "+Inf".into())],
+            value: count(ec.latency.count),
+        });
+        buckets.push(Sample {
+            suffix: "_sum",
+            labels: Vec::new(),
+            value: ec.latency.sum,
+        });
+        buckets.push(Sample {
+            suffix: "_count",
+            labels: Vec::new(),
+            value: count(ec.latency.count),
+        });
+
+        Report(vec![
+            gauge(
+                "uptime_seconds",
+                "Seconds since the daemon started.",
+                vec![Sample::plain(stats.uptime().as_secs_f64().floor())],
+            ),
+            counter(
+                "requests_total",
+                "Requests received, by verb.",
+                stats
+                    .requests
+                    .iter()
+                    .map(|(verb, n)| Sample::labelled("verb", *verb, count(*n)))
+                    .collect(),
+            ),
+            counter(
+                "request_errors_total",
+                "Requests answered with an error, by error code.",
+                stats
+                    .errors
+                    .iter()
+                    .map(|(code, n)| Sample::labelled("code", *code, count(*n)))
+                    .collect(),
+            ),
+            counter(
+                "ec_transactions_total",
+                "Commands sent to the embedded controller.",
+                vec![Sample::plain(count(ec.transactions))],
+            ),
+            counter(
+                "ec_retries_total",
+                "EC status polls that found the controller busy.",
+                vec![Sample::plain(count(ec.retries))],
+            ),
+            counter(
+                "ec_timeouts_total",
+                "EC commands abandoned because the controller stayed busy.",
+                vec![Sample::plain(count(ec.timeouts))],
+            ),
+            counter(
+                "ec_skipped_total",
+                "EC commands not sent because they were still applied.",
+                vec![Sample::plain(count(ec.skipped))],
+            ),
+            Family {
+                name: "ec_transaction_seconds",
+                help: "Time taken by each EC command.",
+                kind: Kind::Histogram,
+                samples: buckets,
+            },
+            gauge(
+                "brightness",
+                "Current brightness level (0-9).",
+                vec![Sample::plain(state.brightness)],
+            ),
+            gauge(
+                "backlight_on",
+                "Whether the backlight is lit.",
+                vec![Sample::plain(u8::from(state.on))],
+            ),
+            gauge(
+                "color_info",
+                "Current color, when known.",
+                state
+                    .color
+                    .iter()
+                    .map(|c| Sample::labelled("color", c.as_str(), 1))
+                    .collect(),
+            ),
+            gauge(
+                "healthy",
+                "Whether the EC answered the last write.",
+                vec![Sample::plain(u8::from(state.healthy))],
+            ),
+            gauge(
+                "queued_requests",
+                "Write requests waiting for the EC.",
+                vec![Sample::plain(count(state.queued as u64))],
+            ),
+        ])
+    }
+
+    /// One `name{labels} value` line per sample.
+    pub fn lines(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .flat_map(|f| {
+                f.samples
+                    .iter()
+                    .map(move |s| format!("{} {}", s.name(f.name), s.value))
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::Array(
+            self.0
+                .iter()
+                .flat_map(|f| {
+                    f.samples.iter().map(move |s| {
+                        Value::Object(vec![
+                            (
+                                "name".into(),
+                                format!("{PREFIX}{}{}", f.name, s.suffix).into(),
+                            ),
+                            (
+                                "labels".into(),
+                                Value::Object(
+                                    s.labels
+                                        .iter()
+                                        .map(|(k, v)| (k.to_string(), v.as_str().into()))
+                                        .collect(),
+                                ),
+                            ),
+                            ("value".into(), s.value.into()),
+                        ])
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// Prometheus text exposition format, with HELP and TYPE lines.
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        for family in &self.0 {
+            let _ = writeln!(text, "# HELP {PREFIX}{} {}", family.name, family.help);
+            let _ = writeln!(
+                text,
+                "# TYPE {PREFIX}{} {}",
+                family.name,
+                family.kind.as_str()
+            );
+            for sample in &family.samples {
+                let _ = writeln!(text, "{} {}", sample.name(family.name), sample.value);
+            }
+        }
+        text
+    }
+}
+
+/// Replaces `path` with the report. The collector may read the file at any
+/// moment, so it is written next to it and renamed into place.
+pub fn write_textfile(path: &Path, report: &Report) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o644)
+        .open(&tmp)?;
+    file.write_all(report.to_prometheus().as_bytes())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
@@ -0,0 +1,200 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! systemd integration: socket activation, readiness notification and
+//! unit templates.
+
+use std::env;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::path::Path;
+use std::time::Duration;
+
+use nix::fcntl::{FcntlArg, FdFlag, fcntl};
+use nix::sys::socket::{SockType, UnixAddr, getsockname, getsockopt, sockopt};
+
+use crate::config::{self, Config};
+use crate::error::{Error, Result};
+use crate::log::warning;
+
+/// First file descriptor passed by the service manager.
+const LISTEN_FDS_START: i32 = 3;
+
+pub const SERVICE_NAME: &str = "gigabyte-kbd-backlight";
+
+/// Takes over the control socket if systemd started us through a `.socket`
+/// unit. Returns `None` when the daemon was started directly.
+pub fn activated_listener() -> Result<Option<UnixListener>> {
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok());
+    // Children must not mistake the sockets for their own.
+    // SAFETY: called during startup, before the daemon starts any threads.
+    unsafe {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+
+    if pid != Some(std::process::id()) {
+        return Ok(None);
+    }
+    match count {
+        None | Some(0) => return Ok(None),
+        Some(1) => {}
+        Some(n) => {
+            return Err(Error::Config(format!(
+                "systemd passed {n} sockets, expected one"
+            )));
+        }
+    }
+
+    // SAFETY: LISTEN_PID names this process, so descriptor 3 was handed to
+    // us by the service manager and nothing else owns it.
+    let fd = unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) };
+    let is_listening_unix_stream = getsockopt(&fd, sockopt::SockType) == Ok(SockType::Stream)
+        && getsockopt(&fd, sockopt::AcceptConn) == Ok(true)
+        && getsockname::<UnixAddr>(fd.as_raw_fd()).is_ok();
+    if !is_listening_unix_stream {
+        return Err(Error::Config(
+            "socket passed by systemd is not a listening Unix stream socket".into(),
+        ));
+    }
+    fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+        .map_err(|e| Error::Io(e.into()))?;
+
+    let listener = UnixListener::from(fd);
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+/// Client side of the service manager's notification protocol (`sd_notify`):
+/// datagrams such as `READY=1` sent to `$NOTIFY_SOCKET`.
+pub struct Notifier {
+    socket: Option<UnixDatagram>,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Connects to `$NOTIFY_SOCKET` if set. Done before dropping privileges,
+    /// so the sandbox never needs to reach the socket by path.
+    pub fn from_env() -> Self {
+        let path = env::var_os("NOTIFY_SOCKET");
+        let watchdog_pid = env::var("WATCHDOG_PID")
+            .ok()
+            .and_then(|p| p.parse::<u32>().ok());
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|_| watchdog_pid.is_none_or(|pid| pid == std::process::id()))
+            .map(Duration::from_micros);
+        // SAFETY: called during startup, before the daemon starts any threads.
+        unsafe {
+            env::remove_var("NOTIFY_SOCKET");
+            env::remove_var("WATCHDOG_USEC");
+            env::remove_var("WATCHDOG_PID");
+        }
+
+        let socket = path.and_then(|path| {
+            let path = path.to_string_lossy().into_owned();
+            let addr = match path.strip_prefix('@') {
+                Some(name) => SocketAddr::from_abstract_name(name),
+                None => SocketAddr::from_pathname(&path),
+            };
+            let socket = UnixDatagram::unbound().and_then(|s| {
+                s.connect_addr(&addr?)?;
+                Ok(s)
+            });
+            match socket {
+                Ok(socket) => Some(socket),
+                Err(e) => {
+                    warning!("cannot reach NOTIFY_SOCKET {path}: {e}");
+                    None
+                }
+            }
+        });
+        Notifier { socket, watchdog }
+    }
+
+    /// Sends newline-separated `KEY=value` assignments. Without a service
+    /// manager this does nothing.
+    pub fn notify(&self, state: &str) {
+        if let Some(socket) = &self.socket
+            && let Err(e) = socket.send(state.as_bytes())
+        {
+            warning!("sd_notify failed: {e}");
+        }
+    }
+
+    /// How often to send `WATCHDOG=1`: half the configured timeout, as
+    /// systemd recommends. `None` when the watchdog is off.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.socket.as_ref()?;
+        self.watchdog.map(|timeout| timeout / 2)
+    }
+}
+
+/// Unit files for running the daemon on demand: a `.socket` unit owning the
+/// control socket and the `.service` it activates.
+pub fn units(config: &Config, config_path: &Path, exe: &Path) -> Vec<(String, String)> {
+    let mut exec = format!("{} daemon", exe.display());
+    if config_path != Path::new(config::DEFAULT_PATH) {
+        exec.push_str(&format!(" --config {}", config_path.display()));
+    }
+    let mode = if config.policy.polkit { "0666" } else { "0660" };
+    let socket = format!(
+        "[Unit]
+Description=Gigabyte keyboard backlight control socket
+
+[Socket]
+ListenStream={socket}
+SocketUser=root
+SocketGroup={group}
+SocketMode={mode}
+DirectoryMode=0755
+RemoveOnStop=yes
+
+[Install]
+WantedBy=sockets.target
+",
+        socket = config.daemon.socket.display(),
+        group = config.daemon.group,
+    );
+    let service = format!(
+        "[Unit]
+Description=Gigabyte keyboard backlight daemon
+Requires={SERVICE_NAME}.socket
+After={SERVICE_NAME}.socket
+
+[Service]
+Type=notify
+ExecStart={exec}
+Restart=on-failure
+RestartSec=3
+WatchdogSec=30
+
+[Install]
+Also={SERVICE_NAME}.socket
+"
+    );
+    vec![
+        (format!("{SERVICE_NAME}.socket"), socket),
+        (format!("{SERVICE_NAME}.service"), service),
+    ]
+}
@@ -0,0 +1,506 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Local time from /etc/localtime.
+//!
+//! The C library reads the zone once per process, so a zone changed with
+//! `timedatectl` would only be noticed after a restart. The TZif file is
+//! small enough to read directly: a table of transitions, then a POSIX TZ
+//! rule for times past the last one.
+
+use std::fs;
+
+pub const LOCALTIME: &str = "/etc/localtime";
+
+const DAY: i64 = 86_400;
+
+pub struct TimeZone {
+    /// UTC times at which the offset changes, ascending.
+    transitions: Vec<i64>,
+    /// Index into `offsets` in effect from each transition.
+    kinds: Vec<u8>,
+    /// UTC offsets in seconds.
+    offsets: Vec<i32>,
+    rule: Option<Rule>,
+}
+
+impl TimeZone {
+    pub fn utc() -> Self {
+        TimeZone {
+            transitions: Vec::new(),
+            kinds: Vec::new(),
+            offsets: vec![0],
+            rule: None,
+        }
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let data = fs::read(LOCALTIME).map_err(|e| format!("{LOCALTIME}: {e}"))?;
+        Self::parse(&data).ok_or_else(|| format!("{LOCALTIME}: not a TZif file"))
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        let header = Header::parse(data)?;
+        if header.version < b'2' {
+            return Self::parse_block(&data[44..], &header, 4, None);
+        }
+        // Version 2 repeats the data with 64-bit times, followed by the rule.
+        let rest = data.get(44 + header.block_len(4)..)?;
+        let header = Header::parse(rest)?;
+        let block = rest.get(44..)?;
+        let footer = block.get(header.block_len(8)..)?;
+        let rule = std::str::from_utf8(footer)
+            .ok()
+            .and_then(|f| f.trim_matches('\n').lines().next())
+            .and_then(Rule::parse);
+        Self::parse_block(block, &header, 8, rule)
+    }
+
+    fn parse_block(block: &[u8], header: &Header, size: usize, rule: Option<Rule>) -> Option<Self> {
+        let times = block.get(..header.timecnt * size)?;
+        let transitions = times
+            .chunks_exact(size)
+            .map(|t| match size {
+                4 => i32::from_be_bytes(t.try_into().unwrap()) as i64,
+                _ => i64::from_be_bytes(t.try_into().unwrap()),
+            })
+            .collect();
+        let kinds_at = header.timecnt * size;
+        let kinds: Vec<u8> = block.get(kinds_at..kinds_at + header.timecnt)?.to_vec();
+        let types_at = kinds_at + header.timecnt;
+        let offsets: Vec<i32> = block
+            .get(types_at..types_at + header.typecnt * 6)?
+            .chunks_exact(6)
+            .map(|t| i32::from_be_bytes([t[0], t[1], t[2], t[3]]))
+            .collect();
+        if offsets.is_empty() || kinds.iter().any(|&k| k as usize >= offsets.len()) {
+            return None;
+        }
+        Some(TimeZone {
+            transitions,
+            kinds,
+            offsets,
+            rule,
+        })
+    }
+
+    /// UTC offset in effect at `utc`, in seconds.
+    pub fn offset(&self, utc: i64) -> i32 {
+        let n = self.transitions.partition_point(|&t| t <= utc);
+        if n == self.transitions.len()
+            && let Some(rule) = &self.rule
+        {
+            return rule.offset(utc);
+        }
+        match n {
+            0 => self.offsets[0],
+            n => self.offsets[self.kinds[n - 1] as usize],
+        }
+    }
+
+    pub fn to_local(&self, utc: i64) -> i64 {
+        utc + self.offset(utc) as i64
+    }
+
+    /// UTC time of a local wall-clock time. A time skipped by a change to
+    /// summer time resolves to the moment the clocks jump, the end of the
+    /// gap; a repeated time resolves to its first occurrence.
+    pub fn to_utc(&self, local: i64) -> i64 {
+        let before = self.offset(local - DAY) as i64;
+        let after = self.offset(local + DAY) as i64;
+        let (first, second) = if before >= after {
+            (before, after)
+        } else {
+            (after, before)
+        };
+        for offset in [first, second] {
+            if self.offset(local - offset) as i64 == offset {
+                return local - offset;
+            }
+        }
+        if first == second {
+            return local - before;
+        }
+        // In a gap: the jump lies between the two readings. Find the first
+        // second that is no longer on the old offset.
+        let (mut old, mut new) = (local - first, local - second);
+        while new - old > 1 {
+            let mid = old + (new - old) / 2;
+            if self.offset(mid) as i64 == second {
+                old = mid;
+            } else {
+                new = mid;
+            }
+        }
+        new
+    }
+}
+
+struct Header {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let count = |i: usize| -> Option<usize> {
+            Some(u32::from_be_bytes(data.get(20 + i * 4..24 + i * 4)?.try_into().ok()?) as usize)
+        };
+        Some(Header {
+            version: *data.get(4)?,
+            isutcnt: count(0)?,
+            isstdcnt: count(1)?,
+            leapcnt: count(2)?,
+            timecnt: count(3)?,
+            typecnt: count(4)?,
+            charcnt: count(5)?,
+        })
+    }
+
+    /// Length of the data block that follows the header, for `size`-byte
+    /// times.
+    fn block_len(&self, size: usize) -> usize {
+        self.timecnt * (size + 1)
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+/// A POSIX TZ string such as `CET-1CEST,M3.5.0,M10.5.0/3`.
+struct Rule {
+    std: i32,
+    dst: Option<Dst>,
+}
+
+struct Dst {
+    offset: i32,
+    start: (Day, i32),
+    end: (Day, i32),
+}
+
+enum Day {
+    /// `Jn`: day 1-365, never counting February 29.
+    Julian(u16),
+    /// `n`: day 0-365, counting February 29.
+    Ordinal(u16),
+    /// `Mm.w.d`: weekday `d` of week `w` (5 is the last) of month `m`.
+    Month(u8, u8, u8),
+}
+
+impl Rule {
+    fn parse(text: &str) -> Option<Self> {
+        let mut rest = text;
+        name(&mut rest)?;
+        let std = -offset(&mut rest)?;
+        if rest.is_empty() {
+            return Some(Rule { std, dst: None });
+        }
+        name(&mut rest)?;
+        let dst_offset = if rest.starts_with(',') || rest.is_empty() {
+            std + 3600
+        } else {
+            -offset(&mut rest)?
+        };
+        // Without dates, POSIX leaves them to the implementation; glibc
+        // uses the US rules.
+        let rest = if rest.is_empty() {
+            ",M3.2.0,M11.1.0"
+        } else {
+            rest
+        };
+        let mut dates = rest.strip_prefix(',')?.split(',');
+        let start = transition(dates.next()?)?;
+        let end = transition(dates.next()?)?;
+        Some(Rule {
+            std,
+            dst: Some(Dst {
+                offset: dst_offset,
+                start,
+                end,
+            }),
+        })
+    }
+
+    fn offset(&self, utc: i64) -> i32 {
+        let Some(dst) = &self.dst else {
+            return self.std;
+        };
+        let (year, _, _) = civil_from_days((utc + self.std as i64).div_euclid(DAY));
+        // Transition times are given in the local time in effect before them.
+        let start = dst.start.0.days(year) * DAY + dst.start.1 as i64 - self.std as i64;
+        let end = dst.end.0.days(year) * DAY + dst.end.1 as i64 - dst.offset as i64;
+        let summer = if start < end {
+            start <= utc && utc < end
+        } else {
+            !(end <= utc && utc < start)
+        };
+        if summer { dst.offset } else { self.std }
+    }
+}
+
+impl Day {
+    /// Days since the epoch of this day in `year`.
+    fn days(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        let leap = is_leap(year);
+        match *self {
+            Day::Julian(n) => jan1 + n as i64 - 1 + (leap && n >= 60) as i64,
+            Day::Ordinal(n) => jan1 + n as i64,
+            Day::Month(month, week, weekday) => {
+                let first = days_from_civil(year, month as u32, 1);
+                let mut day = first
+                    + (weekday as i64 - self::weekday(first) as i64).rem_euclid(7)
+                    + (week as i64 - 1) * 7;
+                let next_month = if month == 12 {
+                    days_from_civil(year + 1, 1, 1)
+                } else {
+                    days_from_civil(year, month as u32 + 1, 1)
+                };
+                while day >= next_month {
+                    day -= 7;
+                }
+                day
+            }
+        }
+    }
+}
+
+/// Skips a zone abbreviation: letters, or anything in angle brackets.
+fn name(rest: &mut &str) -> Option<()> {
+    let len = if let Some(quoted) = rest.strip_prefix('<') {
+        quoted.find('>')? + 2
+    } else {
+        rest.find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len())
+    };
+    if len < 3 {
+        return None;
+    }
+    *rest = &rest[len..];
+    Some(())
+}
+
+/// `[+-]hh[:mm[:ss]]` in seconds.
+fn offset(rest: &mut &str) -> Option<i32> {
+    let len = rest
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '+' | '-' | ':')))
+        .unwrap_or(rest.len());
+    let value = clock(&rest[..len])?;
+    *rest = &rest[len..];
+    Some(value)
+}
+
+fn clock(text: &str) -> Option<i32> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let mut seconds = 0;
+    for (i, part) in digits.split(':').enumerate() {
+        if i > 2 {
+            return None;
+        }
+        seconds += part.parse::<i32>().ok()? * [3600, 60, 1][i];
+    }
+    Some(sign * seconds)
+}
+
+/// `date[/time]`; the time defaults to 02:00.
+fn transition(text: &str) -> Option<(Day, i32)> {
+    let (date, time) = match text.split_once('/') {
+        Some((date, time)) => (date, clock(time)?),
+        None => (text, 7200),
+    };
+    let day = if let Some(n) = date.strip_prefix('J') {
+        Day::Julian(n.parse().ok().filter(|n| (1..=365).contains(n))?)
+    } else if let Some(m) = date.strip_prefix('M') {
+        let mut parts = m.split('.').map(|p| p.parse::<u8>().ok());
+        let (month, week, weekday) = (parts.next()??, parts.next()??, parts.next()??);
+        if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+            return None;
+        }
+        Day::Month(month, week, weekday)
+    } else {
+        Day::Ordinal(date.parse().ok().filter(|&n| n <= 365)?)
+    };
+    Some((day, time))
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Inverse of [`days_from_civil`].
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Day of the week, 0 being Sunday.
+pub fn weekday(days: i64) -> u8 {
+    (days + 4).rem_euclid(7) as u8
+}
+
+#[cfg(test)]
+impl TimeZone {
+    /// A zone without transitions that follows `rule` throughout.
+    pub fn from_rule(rule: &str) -> Self {
+        TimeZone {
+            rule: Some(Rule::parse(rule).expect("valid rule")),
+            ..TimeZone::utc()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CET: &str = "CET-1CEST,M3.5.0,M10.5.0/3";
+    const EST: &str = "EST5EDT,M3.2.0,M11.1.0";
+    const AEST: &str = "AEST-10AEDT,M10.1.0,M4.1.0/3";
+
+    fn at(year: i64, month: u32, day: u32, hour: i64, min: i64) -> i64 {
+        days_from_civil(year, month, day) * DAY + hour * 3600 + min * 60
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule = Rule::parse(CET).unwrap();
+        let dst = rule.dst.as_ref().unwrap();
+        assert_eq!((rule.std, dst.offset), (3600, 7200));
+        assert!(matches!(dst.start, (Day::Month(3, 5, 0), 7200)));
+        assert!(matches!(dst.end, (Day::Month(10, 5, 0), 10800)));
+
+        let rule = Rule::parse(EST).unwrap();
+        assert_eq!((rule.std, rule.dst.unwrap().offset), (-18000, -14400));
+
+        let rule = Rule::parse("<+0330>-3:30").unwrap();
+        assert_eq!(rule.std, 12600);
+        assert!(rule.dst.is_none());
+
+        let rule = Rule::parse("<-03>3<-02>,M3.5.0/-2,M10.5.0/-1").unwrap();
+        let dst = rule.dst.unwrap();
+        assert_eq!((rule.std, dst.offset), (-10800, -7200));
+        assert!(matches!(dst.start, (Day::Month(3, 5, 0), -7200)));
+
+        let rule = Rule::parse("IST-2IDT,M3.4.4/26,M10.5.0").unwrap();
+        assert!(matches!(
+            rule.dst.unwrap().start,
+            (Day::Month(3, 4, 4), 93600)
+        ));
+
+        let rule = Rule::parse("XXX3YYY,J60/0,300").unwrap();
+        let dst = rule.dst.unwrap();
+        assert!(matches!(dst.start, (Day::Julian(60), 0)));
+        assert!(matches!(dst.end, (Day::Ordinal(300), 7200)));
+
+        for bad in [
+            "",
+            "UT0",
+            "CET",
+            "CET-1CEST,M13.1.0,M10.5.0",
+            "CET-1CEST,M3.5.0",
+        ] {
+            assert!(Rule::parse(bad).is_none(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn finds_rule_transitions() {
+        let cases = [
+            (CET, at(2026, 3, 29, 0, 59), 3600),
+            (CET, at(2026, 3, 29, 1, 0), 7200),
+            (CET, at(2026, 10, 25, 0, 59), 7200),
+            (CET, at(2026, 10, 25, 1, 0), 3600),
+            (EST, at(2026, 3, 8, 6, 59), -18000),
+            (EST, at(2026, 3, 8, 7, 0), -14400),
+            (EST, at(2026, 11, 1, 6, 0), -18000),
+            // Southern hemisphere: summer time spans the new year.
+            (AEST, at(2026, 1, 15, 12, 0), 39600),
+            (AEST, at(2026, 7, 15, 12, 0), 36000),
+        ];
+        for (rule, utc, offset) in cases {
+            assert_eq!(
+                TimeZone::from_rule(rule).offset(utc),
+                offset,
+                "{rule} at {utc}"
+            );
+        }
+    }
+
+    #[test]
+    fn resolves_local_times() {
+        let zone = TimeZone::from_rule(CET);
+        let cases = [
+            // Ordinary winter and summer times.
+            (at(2026, 1, 10, 12, 0), at(2026, 1, 10, 11, 0)),
+            (at(2026, 7, 10, 12, 0), at(2026, 7, 10, 10, 0)),
+            // The gap from 02:00 to 03:00 resolves to the jump at 03:00.
+            (at(2026, 3, 29, 1, 59), at(2026, 3, 29, 0, 59)),
+            (at(2026, 3, 29, 2, 0), at(2026, 3, 29, 1, 0)),
+            (at(2026, 3, 29, 2, 30), at(2026, 3, 29, 1, 0)),
+            (at(2026, 3, 29, 3, 0), at(2026, 3, 29, 1, 0)),
+            (at(2026, 3, 29, 3, 30), at(2026, 3, 29, 1, 30)),
+            // 02:00 to 03:00 happens twice; the first, in summer time, wins.
+            (at(2026, 10, 25, 1, 59), at(2026, 10, 24, 23, 59)),
+            (at(2026, 10, 25, 2, 30), at(2026, 10, 25, 0, 30)),
+            (at(2026, 10, 25, 3, 0), at(2026, 10, 25, 2, 0)),
+        ];
+        for (local, utc) in cases {
+            assert_eq!(zone.to_utc(local), utc, "local {local}");
+        }
+    }
+
+    #[test]
+    fn converts_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(days_from_civil(2024, 2, 29)), (2024, 2, 29));
+        assert_eq!(weekday(0), 4);
+        assert_eq!(weekday(days_from_civil(2026, 10, 19)), 1);
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright (C) 2026 Winterhearted
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel uevents from a `NETLINK_KOBJECT_UEVENT` socket.
+//!
+//! The kernel multicasts one datagram per device event: `action@devpath`
+//! followed by NUL-separated `KEY=value` pairs. Only the kernel's own
+//! group is joined; udev's re-broadcasts are not needed. The few kinds the
+//! daemon reacts to are picked out as [`DeviceEvent`]s.
+
+use std::collections::BTreeMap;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+
+use nix::errno::Errno;
+use nix::sys::socket::{
+    AddressFamily, NetlinkAddr, SockFlag, SockProtocol, SockType, bind, recvfrom, socket,
+};
+
+use crate::error::{Error, Result};
+
+/// Multicast group the kernel sends uevents to.
+const KERNEL_GROUP: u32 = 1;
+/// Uevents are limited to a few KiB of environment.
+const MAX_UEVENT: usize = 8192;
+
+/// A uevent the daemon cares about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    /// A power supply went online or offline, or a battery's charge changed.
+    PowerSupply,
+    /// An input device, with its name, was plugged in.
+    InputAdded(String),
+    InputRemoved(String),
+    /// A DRM connector changed, such as a monitor being plugged in.
+    DisplayChanged,
+}
+
+#[derive(Debug)]
+pub struct Uevent {
+    pub action: String,
+    pub devpath: String,
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Uevent {
+    pub fn subsystem(&self) -> Option<&str> {
+        self.vars.get("SUBSYSTEM").map(String::as_str)
+    }
+
+    /// Classifies the event, or `None` for everything else.
+    pub fn kind(&self) -> Option<DeviceEvent> {
+        match (self.subsystem()?, self.action.as_str()) {
+            ("power_supply", _) => Some(DeviceEvent::PowerSupply),
+            // Each input device comes with one event per handler node
+            // (eventN, mouseN); only the device itself carries PRODUCT.
+            ("input", action @ ("add" | "remove")) if self.vars.contains_key("PRODUCT") => {
+                let name = self
+                    .vars
+                    .get("NAME")
+                    .map(|n| n.trim_matches('"').to_string())
+                    .unwrap_or_else(|| self.devpath.clone());
+                Some(if action == "add" {
+                    DeviceEvent::InputAdded(name)
+                } else {
+                    DeviceEvent::InputRemoved(name)
+                })
+            }
+            ("drm", "change") if self.vars.get("HOTPLUG").is_some_and(|v| v == "1") => {
+                Some(DeviceEvent::DisplayChanged)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let mut parts = buf
+            .split(|&b| b == 0)
+            .filter(|part| !part.is_empty())
+            .map(String::from_utf8_lossy);
+        let header = parts.next()?;
+        let (action, devpath) = header.split_once('@')?;
+        Some(Uevent {
+            action: action.to_string(),
+            devpath: devpath.to_string(),
+            vars: parts
+                .filter_map(|part| {
+                    let (key, value) = part.split_once('=')?;
+                    Some((key.to_string(), value.to_string()))
+                })
+                .collect(),
+        })
+    }
+}
+
+pub struct UeventSocket {
+    fd: OwnedFd,
+}
+
+impl UeventSocket {
+    pub fn open() -> Result<Self> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Datagram,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkKObjectUEvent,
+        )
+        .map_err(|e| Error::Io(e.into()))?;
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, KERNEL_GROUP))
+            .map_err(|e| Error::Io(e.into()))?;
+        Ok(UeventSocket { fd })
+    }
+
+    /// Next queued uevent, or `None` once there are none left. Datagrams
+    /// that did not come from the kernel are ignored. `ENOBUFS` means
+    /// some were lost and the caller should re-read whatever it tracks.
+    pub fn receive(&self) -> std::result::Result<Option<Uevent>, Errno> {
+        let mut buf = [0u8; MAX_UEVENT];
+        loop {
+            match recvfrom::<NetlinkAddr>(self.fd.as_raw_fd(), &mut buf) {
+                Ok((n, Some(from))) if from.pid() == 0 => {
+                    if let Some(event) = Uevent::parse(&buf[..n]) {
+                        return Ok(Some(event));
+                    }
+                }
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(Errno::EAGAIN) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl AsFd for UeventSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}